@@ -126,19 +126,20 @@ async fn main() -> ObnizResult<()> {
     println!("✓ Switch state: {switch_state}");
 
     // Set up switch callbacks
-    switch
+    // Each callback stays registered while its subscription is alive
+    let push_subscription = switch
         .on_push(|| {
             println!("Switch pushed!");
         })
         .await?;
 
-    switch
+    let left_subscription = switch
         .on_left(|| {
             println!("Switch moved left!");
         })
         .await?;
 
-    switch
+    let right_subscription = switch
         .on_right(|| {
             println!("Switch moved right!");
         })
@@ -219,7 +220,7 @@ async fn main() -> ObnizResult<()> {
     println!("✓ Stopped all PWM channels");
 
     // Clear callbacks
-    switch.remove_callback()?;
+    drop((push_subscription, left_subscription, right_subscription));
     uart.remove_channel_callback(0)?;
     println!("✓ Removed callbacks");
 
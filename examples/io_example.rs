@@ -32,10 +32,11 @@ async fn main() -> ObnizResult<()> {
 
     // Example 3: Stream mode with callback
     println!("Setting up stream mode on pin 2");
-    io.set_pin_callback(2, |state| {
-        println!("Pin 2 changed to: {state}");
-    })
-    .await?;
+    let pin2_subscription = io
+        .set_pin_callback(2, |state| {
+            println!("Pin 2 changed to: {state}");
+        })
+        .await?;
 
     // Example 4: Advanced pin configuration
     let config = IoConfig {
@@ -59,8 +60,9 @@ async fn main() -> ObnizResult<()> {
     println!("Waiting for 10 seconds to observe pin changes...");
     sleep(Duration::from_secs(10)).await;
 
-    // Clean up
-    io.remove_pin_callback(2)?;
+    // Clean up: dropping the subscription unregisters the callback
+    drop(pin2_subscription);
+    io.disable_pin_stream(2).await?;
 
     println!("Example completed successfully!");
 
@@ -13,6 +13,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         should_fail_connection: false,
         should_timeout: false,
         default_delay_ms: 10,
+        ..Default::default()
     };
 
     // Create mock device
@@ -297,12 +298,12 @@ async fn test_callback_system(mock_device: &MockObniz) -> ObnizResult<()> {
     let callback_triggered_clone = callback_triggered.clone();
 
     mock_device.register_callback("test_callback".to_string(), move |data| {
-        println!("  🔔 Callback triggered with data: {data:?}");
+        println!("  🔔 Callback triggered with response: {data:?}");
         callback_triggered_clone.store(true, Ordering::SeqCst);
     })?;
 
     // Simulate callback trigger
-    let test_data = json!({"pin": 0, "value": true});
+    let test_data = json!({"test_callback": {"pin": 0, "value": true}});
     mock_device
         .server()
         .trigger_callback("test_callback", test_data)
@@ -2,8 +2,13 @@
 use serde_json::json;
 use tokio_tungstenite::tungstenite::protocol::Message;
 
-use crate::error::{validate_pin, ObnizError, ObnizResult};
+use crate::callbacks::Subscription;
+use crate::error::ObnizResult;
 use crate::obniz::Obniz;
+use crate::resources::PinClaim;
+use crate::response::ObnizResponse;
+use crate::stream::{InputStream, StreamOptions, StreamSink};
+use crate::transport::ObnizTransport;
 
 /// AD channel configuration
 #[derive(Debug, Clone)]
@@ -20,13 +25,13 @@ pub struct AdValue {
 
 /// Individual AD channel controller
 #[derive(Debug)]
-pub struct AdChannel {
+pub struct AdChannel<T: ObnizTransport = Obniz> {
     channel: u8,
-    obniz: Obniz,
+    obniz: T,
 }
 
-impl AdChannel {
-    pub fn new(channel: u8, obniz: Obniz) -> Self {
+impl<T: ObnizTransport> AdChannel<T> {
+    pub fn new(channel: u8, obniz: T) -> Self {
         Self { channel, obniz }
     }
 
@@ -34,56 +39,44 @@ impl AdChannel {
         format!("ad{}", self.channel)
     }
 
+    /// Validate the channel; the claim taking its pin until `deinit`
+    fn claim(&self) -> ObnizResult<PinClaim> {
+        self.obniz.hardware_profile().check_ad(self.channel)?;
+        Ok(PinClaim::new(&self.channel_key(), &[self.channel]))
+    }
+
     /// Get current voltage reading
     pub async fn get(&self) -> ObnizResult<f64> {
-        validate_pin(self.channel)?;
+        let claimed = self.obniz.resources().take(&[self.claim()?])?;
 
         let channel_key = self.channel_key();
         let request = json!([{&channel_key: "get"}]);
         let message = Message::from(request.to_string());
 
-        let response = self
-            .obniz
-            .send_await_response(message, channel_key.clone())
-            .await
-            .map_err(|e| ObnizError::Connection(e.to_string()))?;
-
-        // Parse the response to extract the voltage value
-        // Response format is typically [{"ad2": 3.3}]
-        if let Some(array) = response.as_array() {
-            if let Some(first_item) = array.first() {
-                if let Some(value) = first_item.get(&channel_key) {
-                    return value.as_f64().ok_or_else(|| {
-                        ObnizError::Generic("Invalid voltage value in response".to_string())
-                    });
-                }
+        let response = match self.obniz.request(message, channel_key.clone()).await {
+            Ok(response) => response,
+            Err(e) => {
+                claimed.rollback();
+                return Err(e);
             }
-        }
-
-        // Fallback: try direct object access
-        if let Some(value) = response.get(&channel_key) {
-            value
-                .as_f64()
-                .ok_or_else(|| ObnizError::Generic("Invalid voltage value in response".to_string()))
-        } else {
-            Err(ObnizError::Generic(format!(
-                "No response for AD channel {}",
-                self.channel
-            )))
+        };
+        match response {
+            ObnizResponse::Ad { voltage, .. } => Ok(voltage),
+            other => Err(other.unexpected(&channel_key)),
         }
     }
 
     /// Configure AD channel
     pub async fn configure(&self, config: AdConfig) -> ObnizResult<()> {
-        validate_pin(self.channel)?;
+        let claim = self.claim()?;
 
         let channel_key = self.channel_key();
         let request = json!([{&channel_key: {"stream": config.stream}}]);
         let message = Message::from(request.to_string());
 
         self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+            .resources()
+            .with_claims(&[claim], || self.obniz.send_message(message))
     }
 
     /// Enable streaming mode
@@ -96,69 +89,71 @@ impl AdChannel {
         self.configure(AdConfig { stream: false }).await
     }
 
-    /// Register callback for voltage changes (stream mode)
-    pub async fn on_change<F>(&self, callback: F) -> ObnizResult<()>
+    /// Register callback for voltage changes (stream mode), active until the subscription is dropped
+    pub async fn on_change<F>(&self, callback: F) -> ObnizResult<Subscription>
     where
         F: Fn(f64) + Send + Sync + 'static,
     {
-        validate_pin(self.channel)?;
+        self.obniz.hardware_profile().check_ad(self.channel)?;
 
         // Enable stream mode first
         self.enable_stream().await?;
 
-        let channel_key = self.channel_key();
-        let channel_key_clone = channel_key.clone();
+        self.obniz.subscribe(self.channel_key(), move |response| {
+            if let ObnizResponse::Ad { voltage, .. } = response {
+                callback(voltage);
+            }
+        })
+    }
 
-        self.obniz
-            .register_callback(channel_key, move |response| {
-                if let Some(value) = response.get(&channel_key_clone) {
-                    if let Some(voltage) = value.as_f64() {
-                        callback(voltage);
-                    }
-                }
-            })
-            .map_err(|e| ObnizError::CallbackError(e.to_string()))?;
+    /// Voltage readings as a stream (enables stream mode); dropping it unregisters the callback
+    pub async fn voltages(&self) -> ObnizResult<InputStream<f64>> {
+        self.voltages_with(StreamOptions::default()).await
+    }
 
-        Ok(())
+    /// `voltages` with custom buffering
+    pub async fn voltages_with(&self, options: StreamOptions) -> ObnizResult<InputStream<f64>> {
+        let sink = StreamSink::new(options);
+        let writer = sink.clone();
+        let subscription = self.on_change(move |voltage| writer.push(voltage)).await?;
+        Ok(sink.into_stream(subscription))
     }
 
-    /// Remove callback for this channel
+    /// Remove every callback for this channel
     pub fn remove_callback(&self) -> ObnizResult<()> {
-        validate_pin(self.channel)?;
+        self.obniz.hardware_profile().check_ad(self.channel)?;
         let channel_key = self.channel_key();
-        self.obniz
-            .unregister_callback(channel_key)
-            .map_err(|e| ObnizError::CallbackError(e.to_string()))
+        self.obniz.unregister_callback(channel_key)
     }
 
     /// Deinitialize AD channel
     pub async fn deinit(&self) -> ObnizResult<()> {
-        validate_pin(self.channel)?;
+        self.obniz.hardware_profile().check_ad(self.channel)?;
 
         let channel_key = self.channel_key();
         let request = json!([{&channel_key: null}]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)?;
+        self.obniz.resources().release(&channel_key);
+        Ok(())
     }
 }
 
 /// AD manager for handling multiple channels
 #[derive(Debug, Clone)]
-pub struct AdManager {
-    obniz: Obniz,
+pub struct AdManager<T: ObnizTransport = Obniz> {
+    obniz: T,
 }
 
-impl AdManager {
-    pub fn new(obniz: Obniz) -> Self {
+impl<T: ObnizTransport> AdManager<T> {
+    pub fn new(obniz: T) -> Self {
         Self { obniz }
     }
 
-    /// Get specific AD channel (0-11)
-    pub fn channel(&self, channel: u8) -> ObnizResult<AdChannel> {
-        validate_pin(channel)?;
+    /// Get specific AD channel, checked against the board's `ad_channels`
+    pub fn channel(&self, channel: u8) -> ObnizResult<AdChannel<T>> {
+        self.obniz.hardware_profile().check_ad(channel)?;
         Ok(AdChannel::new(channel, self.obniz.clone()))
     }
 
@@ -190,7 +185,11 @@ impl AdManager {
     }
 
     /// Set callback for specific channel
-    pub async fn set_channel_callback<F>(&self, channel: u8, callback: F) -> ObnizResult<()>
+    pub async fn set_channel_callback<F>(
+        &self,
+        channel: u8,
+        callback: F,
+    ) -> ObnizResult<Subscription>
     where
         F: Fn(f64) + Send + Sync + 'static,
     {
@@ -207,22 +206,26 @@ impl AdManager {
         self.channel(channel)?.deinit().await
     }
 
-    /// Deinitialize all channels
+    /// Deinitialize every channel of the board, returning the first failure
+    /// after trying them all
     pub async fn deinit_all(&self) -> ObnizResult<()> {
-        for channel in 0..=11 {
-            if let Err(e) = self.deinit_channel(channel).await {
-                eprintln!("Failed to deinitialize AD channel {channel}: {e}");
-            }
+        let mut result = Ok(());
+        for channel in self.obniz.hardware_profile().ad_channels {
+            let deinit = self.deinit_channel(channel).await;
+            result = result.and(deinit);
         }
-        Ok(())
+        result
     }
 
-    /// Get readings from all channels
+    /// Get readings from every channel of the board
     pub async fn read_all(&self) -> ObnizResult<Vec<AdValue>> {
-        let channels: Vec<u8> = (0..=11).collect();
+        let channels = self.obniz.hardware_profile().ad_channels;
         self.get_voltages(channels).await
     }
+}
 
+// Plain helpers sit on the default transport so callers need no type annotation
+impl AdManager {
     /// Utility function to convert voltage to percentage (0V = 0%, 5V = 100%)
     pub fn voltage_to_percentage(voltage: f64) -> f64 {
         (voltage / 5.0 * 100.0).clamp(0.0, 100.0)
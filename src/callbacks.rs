@@ -0,0 +1,499 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::{ObnizError, ObnizResult};
+use crate::obniz::{CallbackFn, CallbackType, ResponseSender};
+use crate::response::ObnizResponse;
+
+/// Socket a request went out on; its waiters end with that socket's session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum SocketRole {
+    /// The socket `connect` opened (cloud, or the device itself in local mode)
+    #[default]
+    Primary,
+    /// The LAN socket carrying IO traffic in hybrid mode
+    Io,
+}
+
+/// Callbacks registered under one response key
+#[derive(Default)]
+struct KeyCallbacks {
+    /// Pending `send_await_response` calls, oldest first
+    waiters: VecDeque<(SocketRole, ResponseSender)>,
+    /// Stream subscribers (e.g. `on_change`) by subscription id, in registration order
+    subscribers: Vec<(u64, CallbackFn)>,
+}
+
+impl KeyCallbacks {
+    fn is_empty(&self) -> bool {
+        self.waiters.is_empty() && self.subscribers.is_empty()
+    }
+}
+
+/// Fresh id for a persistent callback
+pub(crate) fn next_subscription_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Keeps a callback registered; dropping it unregisters that callback only,
+/// leaving other subscribers on the same key in place
+#[must_use = "dropping a Subscription unregisters its callback"]
+pub struct Subscription {
+    key: String,
+    id: u64,
+    unsubscribe: Option<Box<dyn FnOnce() + Send + Sync>>,
+}
+
+impl Subscription {
+    pub(crate) fn new<F>(key: String, id: u64, unsubscribe: F) -> Self
+    where
+        F: FnOnce() + Send + Sync + 'static,
+    {
+        Self {
+            key,
+            id,
+            unsubscribe: Some(Box::new(unsubscribe)),
+        }
+    }
+
+    /// Key the callback listens on, e.g. `io3`
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Keep the callback registered until `remove_callback` or disconnect
+    pub fn detach(mut self) {
+        self.unsubscribe = None;
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(unsubscribe) = self.unsubscribe.take() {
+            unsubscribe();
+        }
+    }
+}
+
+impl std::fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscription")
+            .field("key", &self.key)
+            .field("id", &self.id)
+            .field("detached", &self.unsubscribe.is_none())
+            .finish()
+    }
+}
+
+/// Response waiters and stream subscribers keyed by `io3`, `ad0`, `system`, ...
+///
+/// Each key keeps a FIFO queue of one-shot waiters next to its persistent
+/// subscribers, so concurrent requests for the same key are answered in order
+/// and never replace each other or a subscriber.
+#[derive(Default)]
+pub struct CallbackRegistry {
+    entries: HashMap<String, KeyCallbacks>,
+}
+
+impl CallbackRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a one-shot waiter or add a persistent subscriber for `key`
+    pub fn register(&mut self, key: String, callback: CallbackType) {
+        self.register_on(SocketRole::Primary, key, callback);
+    }
+
+    /// `register`, tying a waiter to the socket its request goes out on
+    pub(crate) fn register_on(&mut self, socket: SocketRole, key: String, callback: CallbackType) {
+        let entry = self.entries.entry(key).or_default();
+        match callback {
+            CallbackType::OneShot(sender) => entry.waiters.push_back((socket, sender)),
+            CallbackType::Persistent { id, callback } => entry.subscribers.push((id, callback)),
+        }
+    }
+
+    /// Remove every persistent subscriber for `key`; pending waiters stay queued
+    pub fn unregister(&mut self, key: &str) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.subscribers.clear();
+            if entry.is_empty() {
+                self.entries.remove(key);
+            }
+        }
+    }
+
+    /// Remove the subscriber `id` for `key`
+    pub fn unsubscribe(&mut self, key: &str, id: u64) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry
+                .subscribers
+                .retain(|(subscriber, _)| *subscriber != id);
+            if entry.is_empty() {
+                self.entries.remove(key);
+            }
+        }
+    }
+
+    /// Deliver the parsed `response` for `key` to every subscriber and the
+    /// oldest live waiter; subscribers skip responses that failed to parse
+    pub fn dispatch(&mut self, key: &str, response: ObnizResult<ObnizResponse>) {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return;
+        };
+
+        if let Ok(parsed) = &response {
+            for (_, callback_fn) in &entry.subscribers {
+                callback_fn(parsed.clone());
+            }
+        }
+
+        // Waiters whose future was dropped are skipped
+        let mut response = response;
+        while let Some((_, sender)) = entry.waiters.pop_front() {
+            match sender.send(response) {
+                Ok(()) => break,
+                Err(returned) => response = returned,
+            }
+        }
+
+        if entry.is_empty() {
+            self.entries.remove(key);
+        }
+    }
+
+    /// Fail the oldest live waiter of every key belonging to `module`
+    /// (`io3` matches only `io3`, `io` matches `io0`, `io1`, ...)
+    pub fn fail_waiters(&mut self, module: &str, error: impl Fn() -> ObnizError) {
+        let is_index = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+        let names_channel = module.ends_with(|c: char| c.is_ascii_digit());
+        let belongs = |key: &str| {
+            key == module || (!names_channel && key.strip_prefix(module).is_some_and(is_index))
+        };
+
+        for (_, entry) in self.entries.iter_mut().filter(|(key, _)| belongs(key)) {
+            while let Some((_, sender)) = entry.waiters.pop_front() {
+                if sender.send(Err(error())).is_ok() {
+                    break;
+                }
+            }
+        }
+        self.entries.retain(|_, entry| !entry.is_empty());
+    }
+
+    /// Drop waiters for `key` whose receiving future was dropped or timed out
+    pub fn prune_waiters(&mut self, key: &str) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.waiters.retain(|(_, sender)| !sender.is_closed());
+            if entry.is_empty() {
+                self.entries.remove(key);
+            }
+        }
+    }
+
+    /// Drop all pending waiters, keeping subscribers
+    pub fn clear_waiters(&mut self) {
+        for entry in self.entries.values_mut() {
+            entry.waiters.clear();
+        }
+        self.entries.retain(|_, entry| !entry.is_empty());
+    }
+
+    /// Drop the waiters whose request went out on `socket`, keeping the rest
+    pub(crate) fn clear_waiters_on(&mut self, socket: SocketRole) {
+        for entry in self.entries.values_mut() {
+            entry.waiters.retain(|(role, _)| *role != socket);
+        }
+        self.entries.retain(|_, entry| !entry.is_empty());
+    }
+
+    /// Number of pending waiters for `key`
+    pub fn pending(&self, key: &str) -> usize {
+        self.entries.get(key).map_or(0, |entry| entry.waiters.len())
+    }
+
+    /// Check whether `key` has a persistent subscriber
+    pub fn has_subscriber(&self, key: &str) -> bool {
+        self.subscribers(key) > 0
+    }
+
+    /// Number of persistent subscribers for `key`
+    pub fn subscribers(&self, key: &str) -> usize {
+        self.entries
+            .get(key)
+            .map_or(0, |entry| entry.subscribers.len())
+    }
+}
+
+impl std::fmt::Debug for CallbackRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut map = f.debug_map();
+        for (key, entry) in &self.entries {
+            map.entry(
+                key,
+                &format_args!(
+                    "{} waiter(s), {} subscriber(s)",
+                    entry.waiters.len(),
+                    entry.subscribers.len()
+                ),
+            );
+        }
+        map.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::IoManager;
+    use crate::mock::{MockConfig, MockObniz};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn test_waiters_are_answered_in_order() {
+        let mut registry = CallbackRegistry::new();
+        let (tx1, mut rx1) = oneshot::channel();
+        let (tx2, mut rx2) = oneshot::channel();
+        registry.register("io3".to_string(), CallbackType::OneShot(tx1));
+        registry.register("io3".to_string(), CallbackType::OneShot(tx2));
+        assert_eq!(registry.pending("io3"), 2);
+
+        let first = json!([{"io3": true}]);
+        let second = json!([{"io3": false}]);
+        registry.dispatch("io3", ObnizResponse::from_frame(&first, "io3"));
+        assert_eq!(
+            rx1.try_recv().unwrap().unwrap(),
+            ObnizResponse::Io {
+                pin: 3,
+                value: true
+            }
+        );
+        assert!(rx2.try_recv().is_err());
+
+        registry.dispatch("io3", ObnizResponse::from_frame(&second, "io3"));
+        assert_eq!(
+            rx2.try_recv().unwrap().unwrap(),
+            ObnizResponse::Io {
+                pin: 3,
+                value: false
+            }
+        );
+        assert_eq!(registry.pending("io3"), 0);
+    }
+
+    #[test]
+    fn test_waiter_does_not_replace_subscriber() {
+        let mut registry = CallbackRegistry::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        registry.register(
+            "io0".to_string(),
+            CallbackType::Persistent {
+                id: 1,
+                callback: Box::new(move |value| sink.lock().unwrap().push(value)),
+            },
+        );
+
+        let (tx, mut rx) = oneshot::channel();
+        registry.register("io0".to_string(), CallbackType::OneShot(tx));
+
+        let frame = json!([{"io0": true}]);
+        registry.dispatch("io0", ObnizResponse::from_frame(&frame, "io0"));
+        registry.dispatch("io0", ObnizResponse::from_frame(&frame, "io0"));
+        // Subscribers only see responses that parsed
+        let invalid = json!([{"io0": "high"}]);
+        registry.dispatch("io0", ObnizResponse::from_frame(&invalid, "io0"));
+
+        let expected = ObnizResponse::Io {
+            pin: 0,
+            value: true,
+        };
+        assert_eq!(rx.try_recv().unwrap().unwrap(), expected);
+        assert_eq!(*seen.lock().unwrap(), vec![expected.clone(), expected]);
+        assert!(registry.has_subscriber("io0"));
+    }
+
+    #[test]
+    fn test_dropped_waiters_are_skipped() {
+        let mut registry = CallbackRegistry::new();
+        let (tx1, rx1) = oneshot::channel();
+        let (tx2, mut rx2) = oneshot::channel();
+        registry.register("system".to_string(), CallbackType::OneShot(tx1));
+        registry.register("system".to_string(), CallbackType::OneShot(tx2));
+        drop(rx1);
+
+        let frame = json!([{"system": {"pong": 1}}]);
+        registry.dispatch("system", ObnizResponse::from_frame(&frame, "system"));
+        assert_eq!(
+            rx2.try_recv().unwrap().unwrap(),
+            ObnizResponse::System(json!({"pong": 1}))
+        );
+    }
+
+    #[test]
+    fn test_fail_waiters_by_module() {
+        let mut registry = CallbackRegistry::new();
+        let (tx1, mut rx1) = oneshot::channel();
+        let (tx2, mut rx2) = oneshot::channel();
+        let (tx3, mut rx3) = oneshot::channel();
+        registry.register("io3".to_string(), CallbackType::OneShot(tx1));
+        registry.register("io3".to_string(), CallbackType::OneShot(tx2));
+        registry.register("io30".to_string(), CallbackType::OneShot(tx3));
+
+        let error = || ObnizError::Device {
+            module: "io3".to_string(),
+            message: "overcurrent".to_string(),
+        };
+        registry.fail_waiters("io3", error);
+        assert!(matches!(
+            rx1.try_recv().unwrap(),
+            Err(ObnizError::Device { .. })
+        ));
+        assert!(rx2.try_recv().is_err());
+        assert!(rx3.try_recv().is_err());
+
+        registry.fail_waiters("io", error);
+        assert!(rx2.try_recv().unwrap().is_err());
+        assert!(rx3.try_recv().unwrap().is_err());
+        assert_eq!(registry.pending("io3"), 0);
+    }
+
+    #[test]
+    fn test_several_subscribers_per_key() {
+        let mut registry = CallbackRegistry::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        for id in [1, 2] {
+            let sink = seen.clone();
+            registry.register(
+                "io0".to_string(),
+                CallbackType::Persistent {
+                    id,
+                    callback: Box::new(move |_| sink.lock().unwrap().push(id)),
+                },
+            );
+        }
+        assert_eq!(registry.subscribers("io0"), 2);
+
+        let frame = json!([{"io0": true}]);
+        registry.dispatch("io0", ObnizResponse::from_frame(&frame, "io0"));
+        registry.unsubscribe("io0", 1);
+        registry.dispatch("io0", ObnizResponse::from_frame(&frame, "io0"));
+
+        assert_eq!(*seen.lock().unwrap(), vec![1, 2, 2]);
+        registry.unsubscribe("io0", 2);
+        assert!(!registry.has_subscriber("io0"));
+    }
+
+    #[test]
+    fn test_subscription_unsubscribes_on_drop() {
+        let dropped = Arc::new(Mutex::new(0));
+        let counter = dropped.clone();
+        let subscription = Subscription::new("io1".to_string(), 7, move || {
+            *counter.lock().unwrap() += 1;
+        });
+        assert_eq!((subscription.key(), subscription.id()), ("io1", 7));
+        drop(subscription);
+        assert_eq!(*dropped.lock().unwrap(), 1);
+
+        let counter = dropped.clone();
+        Subscription::new("io1".to_string(), 8, move || {
+            *counter.lock().unwrap() += 1;
+        })
+        .detach();
+        assert_eq!(*dropped.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_prune_waiters() {
+        let mut registry = CallbackRegistry::new();
+        let (tx1, rx1) = oneshot::channel();
+        let (tx2, _rx2) = oneshot::channel();
+        registry.register("io1".to_string(), CallbackType::OneShot(tx1));
+        registry.register("io1".to_string(), CallbackType::OneShot(tx2));
+
+        drop(rx1);
+        registry.prune_waiters("io1");
+        assert_eq!(registry.pending("io1"), 1);
+    }
+
+    #[test]
+    fn test_unregister_and_clear_waiters() {
+        let mut registry = CallbackRegistry::new();
+        let (tx, _rx) = oneshot::channel();
+        registry.register("ad0".to_string(), CallbackType::OneShot(tx));
+        registry.register(
+            "ad0".to_string(),
+            CallbackType::Persistent {
+                id: 1,
+                callback: Box::new(|_| {}),
+            },
+        );
+
+        registry.unregister("ad0");
+        assert!(!registry.has_subscriber("ad0"));
+        assert_eq!(registry.pending("ad0"), 1);
+
+        registry.register(
+            "ad0".to_string(),
+            CallbackType::Persistent {
+                id: 1,
+                callback: Box::new(|_| {}),
+            },
+        );
+        registry.clear_waiters();
+        assert_eq!(registry.pending("ad0"), 0);
+        assert!(registry.has_subscriber("ad0"));
+    }
+
+    #[test]
+    fn test_clear_waiters_of_one_socket() {
+        let mut registry = CallbackRegistry::new();
+        let (cloud_tx, _cloud_rx) = oneshot::channel();
+        let (lan_tx, _lan_rx) = oneshot::channel();
+        registry.register("io0".to_string(), CallbackType::OneShot(cloud_tx));
+        registry.register_on(
+            SocketRole::Io,
+            "io0".to_string(),
+            CallbackType::OneShot(lan_tx),
+        );
+
+        registry.clear_waiters_on(SocketRole::Io);
+        assert_eq!(registry.pending("io0"), 1);
+        registry.clear_waiters_on(SocketRole::Primary);
+        assert_eq!(registry.pending("io0"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dropping_one_subscription_keeps_the_others() {
+        let mock = MockObniz::new(MockConfig::default());
+        let first = Arc::new(Mutex::new(Vec::new()));
+        let second = Arc::new(Mutex::new(Vec::new()));
+        let (first_sink, second_sink) = (first.clone(), second.clone());
+
+        let pin = IoManager::new(mock.clone()).pin(3).unwrap();
+        let first_subscription = pin
+            .on_change(move |state| first_sink.lock().unwrap().push(state))
+            .await
+            .unwrap();
+        let _second_subscription = pin
+            .on_change(move |state| second_sink.lock().unwrap().push(state))
+            .await
+            .unwrap();
+
+        let server = mock.server();
+        server.trigger_callback("io3", json!({"io3": true})).await;
+        drop(first_subscription);
+        server.trigger_callback("io3", json!({"io3": false})).await;
+
+        assert_eq!(*first.lock().unwrap(), vec![true]);
+        assert_eq!(*second.lock().unwrap(), vec![true, false]);
+    }
+}
@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Hardware and firmware reported by the device in `ws.ready`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObnizDeviceInfo {
+    /// Hardware type, e.g. `obnizb1` or `esp32w`
+    pub hw: String,
+    /// Firmware version, e.g. `3.5.0`
+    pub firmware: String,
+    pub connected_network: Option<ConnectedNetwork>,
+}
+
+/// Network the device is online through
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectedNetwork {
+    /// Unix time the device came online
+    pub online_at: Option<u64>,
+    /// Network type, e.g. `wirelesslan` or `ethernet`
+    pub net: Option<String>,
+    pub local_ip: Option<String>,
+    pub global_ip: Option<String>,
+    pub wifi: Option<WifiNetwork>,
+}
+
+/// Wi-Fi access point the device is joined to
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WifiNetwork {
+    pub ssid: Option<String>,
+    pub mac_address: Option<String>,
+    pub rssi: Option<i32>,
+}
+
+impl ObnizDeviceInfo {
+    /// Extract device info from a `ws.ready` message, e.g.
+    /// `{"ws": {"ready": true, "obniz": {"hw": "obnizb1", "firmware": "3.5.0"}}}`
+    pub fn from_ws_message(message: &Value) -> Option<Self> {
+        let ws = message.get("ws")?;
+        if !ws.get("ready")?.as_bool()? {
+            return None;
+        }
+
+        // A ready device is reported even if some of its details are malformed;
+        // `hw` and `firmware` are kept when they alone are readable
+        let info = match ws.get("obniz") {
+            Some(obniz) => serde_json::from_value(obniz.clone()).unwrap_or_else(|_| {
+                let field = |name: &str| {
+                    obniz
+                        .get(name)
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                Self {
+                    hw: field("hw"),
+                    firmware: field("firmware"),
+                    ..Self::default()
+                }
+            }),
+            None => Self::default(),
+        };
+        Some(info)
+    }
+
+    /// Firmware version as `(major, minor, patch)`; missing parts count as 0
+    pub fn firmware_version(&self) -> Option<(u32, u32, u32)> {
+        // Pre-release and build suffixes ("3.5.0-beta.1") are ignored
+        let mut parts = self
+            .firmware
+            .trim_start_matches('v')
+            .split(['-', '+'])
+            .next()?
+            .split('.')
+            .map(|part| part.parse::<u32>());
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+        let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+        Some((major, minor, patch))
+    }
+
+    /// Check whether the firmware is at least `major.minor.patch`
+    pub fn firmware_at_least(&self, major: u32, minor: u32, patch: u32) -> bool {
+        self.firmware_version()
+            .is_some_and(|version| version >= (major, minor, patch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_device_info_from_ready_message() {
+        let message = json!({
+            "ws": {
+                "ready": true,
+                "obniz": {
+                    "hw": "obnizb1",
+                    "firmware": "3.5.0",
+                    "connected_network": {
+                        "online_at": 1640995200,
+                        "net": "wirelesslan",
+                        "wifi": {"ssid": "test-wifi"}
+                    }
+                }
+            }
+        });
+
+        let info = ObnizDeviceInfo::from_ws_message(&message).unwrap();
+        assert_eq!(info.hw, "obnizb1");
+        assert_eq!(info.firmware_version(), Some((3, 5, 0)));
+        let network = info.connected_network.unwrap();
+        assert_eq!(network.online_at, Some(1640995200));
+        assert_eq!(network.wifi.unwrap().ssid.as_deref(), Some("test-wifi"));
+
+        assert!(ObnizDeviceInfo::from_ws_message(&json!({"ws": {"ready": false}})).is_none());
+        assert!(ObnizDeviceInfo::from_ws_message(&json!({"io0": true})).is_none());
+    }
+
+    #[test]
+    fn test_malformed_device_info_still_reports_ready() {
+        let message = json!({
+            "ws": {
+                "ready": true,
+                "obniz": {
+                    "hw": "obnizb1",
+                    "firmware": "3.5.0",
+                    "connected_network": {"online_at": "yesterday"}
+                }
+            }
+        });
+
+        let info = ObnizDeviceInfo::from_ws_message(&message).unwrap();
+        assert_eq!(info.hw, "obnizb1");
+        assert_eq!(info.firmware, "3.5.0");
+        assert_eq!(info.connected_network, None);
+
+        let info =
+            ObnizDeviceInfo::from_ws_message(&json!({"ws": {"ready": true, "obniz": 1}})).unwrap();
+        assert_eq!(info, ObnizDeviceInfo::default());
+    }
+
+    #[test]
+    fn test_firmware_comparison() {
+        let info = ObnizDeviceInfo {
+            firmware: "3.4".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(info.firmware_version(), Some((3, 4, 0)));
+        assert!(info.firmware_at_least(3, 0, 0));
+        assert!(!info.firmware_at_least(3, 5, 0));
+
+        let beta = ObnizDeviceInfo {
+            firmware: "v3.5.1-beta.2".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(beta.firmware_version(), Some((3, 5, 1)));
+
+        let unknown = ObnizDeviceInfo::default();
+        assert_eq!(unknown.firmware_version(), None);
+        assert!(!unknown.firmware_at_least(0, 0, 0));
+    }
+}
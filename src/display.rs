@@ -4,6 +4,7 @@ use tokio_tungstenite::tungstenite::protocol::Message;
 
 use crate::error::{ObnizError, ObnizResult};
 use crate::obniz::Obniz;
+use crate::transport::ObnizTransport;
 
 /// QR code error correction levels
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -48,17 +49,19 @@ pub struct PinAssignment {
 
 /// Display manager for obniz device
 #[derive(Debug, Clone)]
-pub struct DisplayManager {
-    obniz: Obniz,
+pub struct DisplayManager<T: ObnizTransport = Obniz> {
+    obniz: T,
 }
 
-impl DisplayManager {
-    pub fn new(obniz: Obniz) -> Self {
+impl<T: ObnizTransport> DisplayManager<T> {
+    pub fn new(obniz: T) -> Self {
         Self { obniz }
     }
 
     /// Display text on the obniz screen
     pub async fn text(&self, text: &str) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         if text.is_empty() {
             return Err(ObnizError::Generic("Text cannot be empty".to_string()));
         }
@@ -66,23 +69,23 @@ impl DisplayManager {
         let request = json!([{"display": {"text": text}}]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Clear the display
     pub async fn clear(&self) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         let request = json!([{"display": {"clear": true}}]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Display a QR code with specified error correction level
     pub async fn qr(&self, text: &str, correction_type: QrCorrectionType) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         if text.is_empty() {
             return Err(ObnizError::Generic("QR text cannot be empty".to_string()));
         }
@@ -97,13 +100,13 @@ impl DisplayManager {
         }]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Display raw pixel data
     pub async fn raw(&self, config: RawDisplayConfig) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         if config.data.is_empty() {
             return Err(ObnizError::Generic("Raw data cannot be empty".to_string()));
         }
@@ -139,13 +142,13 @@ impl DisplayManager {
         }]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Set display brightness (0-100)
     pub async fn brightness(&self, level: u8) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         if level > 100 {
             return Err(ObnizError::Generic(
                 "Brightness level must be between 0-100".to_string(),
@@ -159,13 +162,13 @@ impl DisplayManager {
         }]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Set display contrast (0-100)
     pub async fn contrast(&self, level: u8) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         if level > 100 {
             return Err(ObnizError::Generic(
                 "Contrast level must be between 0-100".to_string(),
@@ -179,23 +182,22 @@ impl DisplayManager {
         }]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Assign pins for display modules
     pub async fn pin_assign(&self, assignments: Vec<PinAssignment>) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         if assignments.is_empty() {
             return Err(ObnizError::Generic(
                 "Pin assignments cannot be empty".to_string(),
             ));
         }
 
+        let hardware = self.obniz.hardware_profile();
         for assignment in &assignments {
-            if assignment.pin > 11 {
-                return Err(ObnizError::InvalidPin(assignment.pin));
-            }
+            hardware.check_io(assignment.pin)?;
             if assignment.module_name.is_empty() || assignment.pin_name.is_empty() {
                 return Err(ObnizError::Generic(
                     "Module name and pin name cannot be empty".to_string(),
@@ -213,13 +215,13 @@ impl DisplayManager {
         let request = json!([pin_config]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Draw a pixel at specified coordinates
     pub async fn pixel(&self, x: u16, y: u16, color: bool) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         let request = json!([{
             "display": {
                 "pixel": {
@@ -231,13 +233,13 @@ impl DisplayManager {
         }]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Draw a line from (x1, y1) to (x2, y2)
     pub async fn line(&self, x1: u16, y1: u16, x2: u16, y2: u16, color: bool) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         let request = json!([{
             "display": {
                 "line": {
@@ -251,9 +253,7 @@ impl DisplayManager {
         }]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Draw a rectangle
@@ -266,6 +266,8 @@ impl DisplayManager {
         filled: bool,
         color: bool,
     ) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         if width == 0 || height == 0 {
             return Err(ObnizError::Generic(
                 "Width and height must be greater than 0".to_string(),
@@ -286,9 +288,7 @@ impl DisplayManager {
         }]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Draw a circle
@@ -300,6 +300,8 @@ impl DisplayManager {
         filled: bool,
         color: bool,
     ) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         if radius == 0 {
             return Err(ObnizError::Generic(
                 "Radius must be greater than 0".to_string(),
@@ -319,13 +321,13 @@ impl DisplayManager {
         }]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Set text size
     pub async fn text_size(&self, size: u8) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         if size == 0 {
             return Err(ObnizError::Generic(
                 "Text size must be greater than 0".to_string(),
@@ -339,13 +341,13 @@ impl DisplayManager {
         }]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Set text position
     pub async fn text_pos(&self, x: u16, y: u16) -> ObnizResult<()> {
+        self.obniz.hardware_profile().check_display()?;
+
         let request = json!([{
             "display": {
                 "text_pos": {
@@ -356,9 +358,7 @@ impl DisplayManager {
         }]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 }
 
@@ -0,0 +1,378 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+
+use crate::callbacks::{next_subscription_id, Subscription};
+use crate::error::ObnizResult;
+use crate::io::IoPin;
+use crate::transport::ObnizTransport;
+
+/// Level transitions to report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// Low to high
+    Rising,
+    /// High to low
+    Falling,
+    Both,
+}
+
+impl Edge {
+    /// Whether a change to `level` is an edge of this kind
+    pub fn matches(&self, level: bool) -> bool {
+        match self {
+            Edge::Rising => level,
+            Edge::Falling => !level,
+            Edge::Both => true,
+        }
+    }
+}
+
+/// Turns raw pin states into debounced edges. A new level is accepted once it
+/// has held for `debounce`; bounces back to the current level inside the window
+/// are dropped. Feed states with `update` and call `tick` by `next_deadline`.
+#[derive(Debug, Clone)]
+pub struct EdgeDetector {
+    edge: Edge,
+    debounce: Duration,
+    /// Last accepted level; the first state reported only sets it
+    level: Option<bool>,
+    /// Level waiting out the debounce window, and when it was first seen
+    pending: Option<(bool, Instant)>,
+}
+
+impl EdgeDetector {
+    pub fn new(edge: Edge, debounce: Duration) -> Self {
+        Self {
+            edge,
+            debounce,
+            level: None,
+            pending: None,
+        }
+    }
+
+    /// Last accepted level
+    pub fn level(&self) -> Option<bool> {
+        self.level
+    }
+
+    /// Handle a pin state reported at `now`; returns the new level on a matching edge
+    pub fn update(&mut self, level: bool, now: Instant) -> Option<bool> {
+        let settled = self.tick(now);
+        if self.debounce.is_zero() || self.level.is_none() {
+            return settled.or(self.accept(level));
+        }
+
+        if self.pending.map(|(pending, _)| pending) == Some(level) {
+            return settled;
+        }
+        self.pending = (self.level != Some(level)).then_some((level, now));
+        settled
+    }
+
+    /// Accept a pending level whose debounce window has passed by `now`
+    pub fn tick(&mut self, now: Instant) -> Option<bool> {
+        match self.pending {
+            Some((level, since)) if now.duration_since(since) >= self.debounce => {
+                self.pending = None;
+                self.accept(level)
+            }
+            _ => None,
+        }
+    }
+
+    /// Next instant at which `tick` may report an edge
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.map(|(_, since)| since + self.debounce)
+    }
+
+    fn accept(&mut self, level: bool) -> Option<bool> {
+        let previous = self.level.replace(level);
+        (previous.is_some_and(|previous| previous != level) && self.edge.matches(level))
+            .then_some(level)
+    }
+}
+
+/// Settings for `IoPin::counter`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterConfig {
+    pub edge: Edge,
+    pub debounce: Duration,
+    /// Span `IoCounter::rate` averages over
+    pub rate_window: Duration,
+}
+
+impl Default for CounterConfig {
+    fn default() -> Self {
+        Self {
+            edge: Edge::Rising,
+            debounce: Duration::ZERO,
+            rate_window: Duration::from_secs(1),
+        }
+    }
+}
+
+impl CounterConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn edge(mut self, edge: Edge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    pub fn rate_window(mut self, window: Duration) -> Self {
+        self.rate_window = window;
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pulses {
+    total: AtomicU64,
+    /// Pulse times inside the rate window, oldest first
+    recent: Mutex<VecDeque<Instant>>,
+}
+
+/// Counts pulses on a pin, e.g. from a flow meter or reed switch.
+/// Counting stops when the counter is dropped.
+#[derive(Debug)]
+pub struct IoCounter {
+    pulses: Arc<Pulses>,
+    rate_window: Duration,
+    _subscription: Subscription,
+}
+
+impl IoCounter {
+    /// Pulses since the counter started or was last reset
+    pub fn total(&self) -> u64 {
+        self.pulses.total.load(Ordering::Relaxed)
+    }
+
+    /// Pulses per second over the last `rate_window`
+    pub fn rate(&self) -> f64 {
+        if self.rate_window.is_zero() {
+            return 0.0;
+        }
+        let mut recent = self.pulses.recent.lock().unwrap();
+        prune(&mut recent, Instant::now(), self.rate_window);
+        recent.len() as f64 / self.rate_window.as_secs_f64()
+    }
+
+    pub fn reset(&self) {
+        self.pulses.total.store(0, Ordering::Relaxed);
+        self.pulses.recent.lock().unwrap().clear();
+    }
+}
+
+fn prune(recent: &mut VecDeque<Instant>, now: Instant, window: Duration) {
+    while recent
+        .front()
+        .is_some_and(|at| now.duration_since(*at) > window)
+    {
+        recent.pop_front();
+    }
+}
+
+impl<T: ObnizTransport> IoPin<T> {
+    /// Call `callback` with the new level on each debounced `edge`, until the
+    /// subscription is dropped. Enables stream mode; a zero `debounce` disables filtering.
+    pub async fn on_edge<F>(
+        &self,
+        edge: Edge,
+        debounce: Duration,
+        callback: F,
+    ) -> ObnizResult<Subscription>
+    where
+        F: Fn(bool) + Send + Sync + 'static,
+    {
+        let mut changes = self.changes().await?;
+        let task = tokio::spawn(async move {
+            let mut detector = EdgeDetector::new(edge, debounce);
+            loop {
+                let next = match detector.next_deadline() {
+                    Some(deadline) => {
+                        let deadline = tokio::time::Instant::from_std(deadline);
+                        match tokio::time::timeout_at(deadline, changes.next()).await {
+                            Ok(next) => next,
+                            Err(_) => {
+                                detector
+                                    .tick(Instant::now())
+                                    .into_iter()
+                                    .for_each(&callback);
+                                continue;
+                            }
+                        }
+                    }
+                    None => changes.next().await,
+                };
+                let Some(level) = next else { break };
+                detector
+                    .update(level, Instant::now())
+                    .into_iter()
+                    .for_each(&callback);
+            }
+        });
+
+        Ok(Subscription::new(
+            self.pin_key(),
+            next_subscription_id(),
+            move || task.abort(),
+        ))
+    }
+
+    /// Call `callback` on each debounced low-to-high transition
+    pub async fn on_rising<F>(&self, debounce: Duration, callback: F) -> ObnizResult<Subscription>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.on_edge(Edge::Rising, debounce, move |_| callback())
+            .await
+    }
+
+    /// Call `callback` on each debounced high-to-low transition
+    pub async fn on_falling<F>(&self, debounce: Duration, callback: F) -> ObnizResult<Subscription>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.on_edge(Edge::Falling, debounce, move |_| callback())
+            .await
+    }
+
+    /// Count debounced edges on this pin
+    pub async fn counter(&self, config: CounterConfig) -> ObnizResult<IoCounter> {
+        let pulses = Arc::new(Pulses::default());
+        let sink = pulses.clone();
+        let rate_window = config.rate_window;
+
+        let subscription = self
+            .on_edge(config.edge, config.debounce, move |_| {
+                let now = Instant::now();
+                sink.total.fetch_add(1, Ordering::Relaxed);
+                let mut recent = sink.recent.lock().unwrap();
+                recent.push_back(now);
+                prune(&mut recent, now, rate_window);
+            })
+            .await?;
+
+        Ok(IoCounter {
+            pulses,
+            rate_window,
+            _subscription: subscription,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::IoManager;
+    use crate::mock::{MockConfig, MockObniz};
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_edges_without_debounce() {
+        let start = Instant::now();
+        let mut rising = EdgeDetector::new(Edge::Rising, Duration::ZERO);
+        let mut both = EdgeDetector::new(Edge::Both, Duration::ZERO);
+
+        // The first state only sets the level
+        assert_eq!(rising.update(false, start), None);
+        assert_eq!(both.update(false, start), None);
+
+        for (level, expected_rising, expected_both) in [
+            (true, Some(true), Some(true)),
+            (true, None, None),
+            (false, None, Some(false)),
+        ] {
+            assert_eq!(rising.update(level, start), expected_rising);
+            assert_eq!(both.update(level, start), expected_both);
+        }
+    }
+
+    #[test]
+    fn test_debounce_waits_for_stable_level() {
+        let start = Instant::now();
+        let mut detector = EdgeDetector::new(Edge::Falling, ms(20));
+        // The first state is taken at once as the baseline
+        detector.update(true, start);
+        assert_eq!(detector.level(), Some(true));
+        assert_eq!(detector.next_deadline(), None);
+
+        // Contact bounce: low, high, low within the window
+        assert_eq!(detector.update(false, start + ms(100)), None);
+        assert_eq!(detector.update(true, start + ms(105)), None);
+        assert_eq!(detector.next_deadline(), None);
+        assert_eq!(detector.update(false, start + ms(110)), None);
+        assert_eq!(detector.next_deadline(), Some(start + ms(130)));
+
+        assert_eq!(detector.tick(start + ms(125)), None);
+        assert_eq!(detector.tick(start + ms(130)), Some(false));
+        assert_eq!(detector.level(), Some(false));
+    }
+
+    #[test]
+    fn test_rising_ignores_falling_after_debounce() {
+        let start = Instant::now();
+        let mut detector = EdgeDetector::new(Edge::Rising, ms(10));
+        detector.update(false, start);
+
+        detector.update(true, start + ms(50));
+        assert_eq!(detector.update(true, start + ms(70)), Some(true));
+        detector.update(false, start + ms(100));
+        assert_eq!(detector.tick(start + ms(200)), None);
+        assert_eq!(detector.level(), Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_io_edges_and_counter() {
+        let mock = MockObniz::new(MockConfig::default());
+        let server = mock.server();
+        let io = IoManager::new(mock.clone());
+
+        let meter = io.pin(4).unwrap();
+        let counter = meter.counter(CounterConfig::new()).await.unwrap();
+        let button = io.pin(5).unwrap();
+        let (sender, mut releases) = mpsc::unbounded_channel();
+        let _falling = button
+            .on_falling(Duration::from_millis(20), move || {
+                let _ = sender.send(());
+            })
+            .await
+            .unwrap();
+
+        for value in [false, true, false, true, false, true] {
+            server.trigger_callback("io4", json!({"io4": value})).await;
+        }
+        // A bouncy release settles low once
+        for value in [true, false, true, false] {
+            server.trigger_callback("io5", json!({"io5": value})).await;
+        }
+
+        tokio::time::timeout(Duration::from_secs(1), releases.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(releases.try_recv().is_err());
+
+        // The first reported state is only a baseline
+        assert_eq!(counter.total(), 3);
+        assert_eq!(counter.rate(), 3.0);
+        counter.reset();
+        assert_eq!(counter.total(), 0);
+    }
+}
@@ -30,6 +30,24 @@ pub enum ObnizError {
     /// Permission denied
     PermissionDenied,
 
+    /// Pin that does not exist on the connected board
+    UnsupportedPin { pin: u8, hardware: String },
+
+    /// Pin already owned by another module (e.g. `uart0`)
+    PinInUse { pin: u8, owner: String },
+
+    /// Channel held by a live `allocate` result (e.g. `pwm0`); use that handle or drop it first
+    ChannelInUse { channel: String },
+
+    /// Peripheral the connected board does not have (e.g. a display)
+    UnsupportedFeature { feature: String, hardware: String },
+
+    /// Error reported by the device firmware for a module
+    Device { module: String, message: String },
+
+    /// Malformed or unexpected message from the device
+    InvalidResponse { key: String, reason: String },
+
     /// Generic error with message
     Generic(String),
 }
@@ -48,6 +66,27 @@ impl fmt::Display for ObnizError {
             ObnizError::CallbackError(msg) => write!(f, "Callback error: {msg}"),
             ObnizError::DeviceNotFound(id) => write!(f, "Device not found: {id}"),
             ObnizError::PermissionDenied => write!(f, "Permission denied"),
+            ObnizError::UnsupportedPin { pin, hardware } => {
+                write!(f, "Pin {pin} is not available on {hardware}")
+            }
+            ObnizError::PinInUse { pin, owner } => {
+                write!(f, "Pin {pin} is already in use by {owner}")
+            }
+            ObnizError::ChannelInUse { channel } => {
+                write!(
+                    f,
+                    "{channel} is held by an allocation; use the allocation or drop it first"
+                )
+            }
+            ObnizError::UnsupportedFeature { feature, hardware } => {
+                write!(f, "{feature} is not supported on {hardware}")
+            }
+            ObnizError::Device { module, message } => {
+                write!(f, "Device error in {module}: {message}")
+            }
+            ObnizError::InvalidResponse { key, reason } => {
+                write!(f, "Invalid response for {key}: {reason}")
+            }
             ObnizError::Generic(msg) => write!(f, "Error: {msg}"),
         }
     }
@@ -0,0 +1,310 @@
+use std::sync::{Arc, Mutex};
+
+use futures::Stream;
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+/// Capacity of the event broadcast buffer; slow subscribers skip older events
+pub(crate) const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// Connection lifecycle events emitted by the WebSocket handler
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    /// Opening a socket to the obniz cloud
+    Connecting,
+    /// The cloud redirected us to a device-specific host
+    Redirected { host: String },
+    /// The device reported `ws.ready`
+    Ready { hw: String, firmware: String },
+    /// The socket dropped
+    Disconnected { reason: String },
+    /// A new socket was opened after a disconnect
+    Reconnected,
+    /// The handler stopped; no further events will follow
+    Closed,
+    /// Reconnect attempt `attempt` (1-based) failed
+    ReconnectFailed { attempt: u32, reason: String },
+    /// The LAN socket of hybrid mode could not be opened, so IO goes over the cloud
+    LocalUnavailable { ip: String, reason: String },
+    /// An incoming message could not be handled and was dropped
+    InvalidMessage { reason: String },
+}
+
+/// Where the connection stands, as left by the last lifecycle event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Opening a socket or following a redirect
+    Connecting,
+    /// A socket to the device is open
+    Connected,
+    /// The socket dropped; reconnecting if a policy is set
+    Disconnected,
+    /// The handler stopped for good
+    Closed,
+}
+
+impl ConnectionState {
+    /// State after `event`, or None if the event leaves it unchanged
+    pub fn after(event: &ConnectionEvent) -> Option<Self> {
+        match event {
+            ConnectionEvent::Connecting | ConnectionEvent::Redirected { .. } => {
+                Some(ConnectionState::Connecting)
+            }
+            ConnectionEvent::Ready { .. } | ConnectionEvent::Reconnected => {
+                Some(ConnectionState::Connected)
+            }
+            ConnectionEvent::Disconnected { .. } | ConnectionEvent::ReconnectFailed { .. } => {
+                Some(ConnectionState::Disconnected)
+            }
+            ConnectionEvent::Closed => Some(ConnectionState::Closed),
+            ConnectionEvent::LocalUnavailable { .. } | ConnectionEvent::InvalidMessage { .. } => {
+                None
+            }
+        }
+    }
+}
+
+/// Broadcasts lifecycle events and keeps the state they leave the connection in.
+/// Clones share both, so a builder can hand its subscribers on to the connection.
+#[derive(Debug, Clone)]
+pub(crate) struct LifecycleEvents {
+    sender: broadcast::Sender<ConnectionEvent>,
+    state: Arc<Mutex<ConnectionState>>,
+}
+
+impl LifecycleEvents {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            sender,
+            state: Arc::new(Mutex::new(ConnectionState::Connecting)),
+        }
+    }
+
+    pub fn emit(&self, event: ConnectionEvent) {
+        if let Some(state) = ConnectionState::after(&event) {
+            self.set_state(state);
+        }
+        // No subscribers is not an error
+        let _ = self.sender.send(event);
+    }
+
+    pub fn set_state(&self, state: ConnectionState) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn subscribe(&self) -> impl Stream<Item = ConnectionEvent> + Send + 'static {
+        broadcast_stream(self.sender.subscribe())
+    }
+}
+
+impl ConnectionEvent {
+    /// Extract a `Ready` event from a `ws` message, e.g.
+    /// `{"ws": {"ready": true, "obniz": {"hw": "obnizb1", "firmware": "3.5.0"}}}`
+    pub fn from_ws_message(message: &Value) -> Option<Self> {
+        let ws = message.get("ws")?;
+        if !ws.get("ready")?.as_bool()? {
+            return None;
+        }
+
+        let obniz = ws.get("obniz");
+        let field = |name: &str| {
+            obniz
+                .and_then(|obniz| obniz.get(name))
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        Some(ConnectionEvent::Ready {
+            hw: field("hw"),
+            firmware: field("firmware"),
+        })
+    }
+}
+
+/// Severity of a firmware `debug` report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceErrorLevel {
+    Error,
+    Warning,
+}
+
+/// Error or warning reported by the device firmware (overcurrent, invalid arguments, ...)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceError {
+    pub level: DeviceErrorLevel,
+    /// Module the report refers to, e.g. `io3`, when the device names one
+    pub module: Option<String>,
+    pub message: String,
+}
+
+impl DeviceError {
+    /// Extract reports from a `debug` message, e.g.
+    /// `{"debug": {"error": {"module": "io3", "message": "overcurrent"}}}`
+    pub fn from_debug_message(message: &Value) -> Vec<Self> {
+        let Some(debug) = message.get("debug") else {
+            return Vec::new();
+        };
+
+        [
+            ("error", DeviceErrorLevel::Error),
+            ("warning", DeviceErrorLevel::Warning),
+        ]
+        .into_iter()
+        .filter_map(|(field, level)| {
+            let report = debug.get(field)?;
+            let message = match report {
+                Value::String(text) => text.clone(),
+                _ => report
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| report.to_string()),
+            };
+            // Older firmware only names the module at the start of the text ("io3 overcurrent")
+            let module = report
+                .get("module")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .or_else(|| message.split_whitespace().next().and_then(module_key));
+            Some(DeviceError {
+                level,
+                module,
+                message,
+            })
+        })
+        .collect()
+    }
+}
+
+/// `io3` or `uart0` style word, as used for response keys
+fn module_key(word: &str) -> Option<String> {
+    let word = word.trim_end_matches(':');
+    let digits = word.trim_start_matches(|c: char| c.is_ascii_lowercase());
+    let is_key = digits.len() < word.len()
+        && !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit());
+    is_key.then(|| word.to_string())
+}
+
+/// Turn a broadcast receiver into a stream, skipping events lost to lag
+pub(crate) fn broadcast_stream<T>(receiver: broadcast::Receiver<T>) -> impl Stream<Item = T>
+where
+    T: Clone + Send + 'static,
+{
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use serde_json::json;
+
+    #[test]
+    fn test_ready_event_parsing() {
+        let message = json!({
+            "ws": {
+                "ready": true,
+                "obniz": {"hw": "obnizb1", "firmware": "3.5.0"}
+            }
+        });
+
+        assert_eq!(
+            ConnectionEvent::from_ws_message(&message),
+            Some(ConnectionEvent::Ready {
+                hw: "obnizb1".to_string(),
+                firmware: "3.5.0".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_non_ready_messages_are_ignored() {
+        assert_eq!(
+            ConnectionEvent::from_ws_message(&json!({"ws": {"redirect": "wss://x"}})),
+            None
+        );
+        assert_eq!(
+            ConnectionEvent::from_ws_message(&json!({"io0": true})),
+            None
+        );
+    }
+
+    #[test]
+    fn test_debug_message_parsing() {
+        let message = json!({
+            "debug": {
+                "error": {"module": "io3", "message": "overcurrent"},
+                "warning": {"message": "ad0: voltage out of range"}
+            }
+        });
+
+        assert_eq!(
+            DeviceError::from_debug_message(&message),
+            vec![
+                DeviceError {
+                    level: DeviceErrorLevel::Error,
+                    module: Some("io3".to_string()),
+                    message: "overcurrent".to_string(),
+                },
+                DeviceError {
+                    level: DeviceErrorLevel::Warning,
+                    module: Some("ad0".to_string()),
+                    message: "ad0: voltage out of range".to_string(),
+                },
+            ]
+        );
+
+        let unnamed = DeviceError::from_debug_message(&json!({"debug": {"error": "invalid json"}}));
+        assert_eq!(unnamed[0].module, None);
+        assert!(DeviceError::from_debug_message(&json!({"io0": true})).is_empty());
+    }
+
+    #[test]
+    fn test_lifecycle_events_track_state() {
+        let events = LifecycleEvents::new();
+        assert_eq!(events.state(), ConnectionState::Connecting);
+
+        events.emit(ConnectionEvent::Reconnected);
+        assert_eq!(events.state(), ConnectionState::Connected);
+        events.emit(ConnectionEvent::InvalidMessage {
+            reason: "bad json".to_string(),
+        });
+        assert_eq!(events.state(), ConnectionState::Connected);
+        events.emit(ConnectionEvent::ReconnectFailed {
+            attempt: 1,
+            reason: "refused".to_string(),
+        });
+        assert_eq!(events.state(), ConnectionState::Disconnected);
+        events.emit(ConnectionEvent::Closed);
+        assert_eq!(events.clone().state(), ConnectionState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_stream_delivers_events() {
+        let (sender, receiver) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let mut stream = Box::pin(broadcast_stream(receiver));
+
+        sender.send(ConnectionEvent::Connecting).unwrap();
+        sender.send(ConnectionEvent::Closed).unwrap();
+        drop(sender);
+
+        assert_eq!(stream.next().await, Some(ConnectionEvent::Connecting));
+        assert_eq!(stream.next().await, Some(ConnectionEvent::Closed));
+        assert_eq!(stream.next().await, None);
+    }
+}
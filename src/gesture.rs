@@ -0,0 +1,302 @@
+use std::time::{Duration, Instant};
+
+use crate::switch::SwitchState;
+
+/// Thresholds for `GestureRecognizer`, measured on the host clock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GestureConfig {
+    /// Longest gap between the first release and the second press of a double click
+    pub double_click: Duration,
+    /// Hold time after which a press counts as a long press
+    pub long_press: Duration,
+    /// Time between `HoldRepeat` gestures once a hold passes `long_press`
+    pub repeat_interval: Duration,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            double_click: Duration::from_millis(300),
+            long_press: Duration::from_millis(800),
+            repeat_interval: Duration::from_millis(200),
+        }
+    }
+}
+
+impl GestureConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn double_click(mut self, window: Duration) -> Self {
+        self.double_click = window;
+        self
+    }
+
+    pub fn long_press(mut self, threshold: Duration) -> Self {
+        self.long_press = threshold;
+        self
+    }
+
+    pub fn repeat_interval(mut self, interval: Duration) -> Self {
+        self.repeat_interval = interval;
+        self
+    }
+}
+
+/// Gesture made with one switch direction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Gesture {
+    /// Short press not followed by a second one within `double_click`
+    Click,
+    DoubleClick,
+    /// Press held for at least `long_press`, reported on release with the hold time
+    LongPress(Duration),
+    /// Emitted every `repeat_interval` while a long press is held
+    HoldRepeat,
+}
+
+/// Gesture and the switch direction (`Push`, `Left` or `Right`) that made it
+#[derive(Debug, Clone, PartialEq)]
+pub struct GestureEvent {
+    pub direction: SwitchState,
+    pub gesture: Gesture,
+}
+
+#[derive(Debug, Clone)]
+struct Press {
+    direction: SwitchState,
+    at: Instant,
+    /// Second press of a possible double click
+    second: bool,
+    next_repeat: Instant,
+}
+
+/// Turns switch states into gestures. Feed it every state with `update` and
+/// call `tick` by `next_deadline` so clicks and hold-repeats fire on time.
+#[derive(Debug, Clone)]
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    press: Option<Press>,
+    /// Released short press waiting to see whether a second one follows
+    pending_click: Option<(SwitchState, Instant)>,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            press: None,
+            pending_click: None,
+        }
+    }
+
+    /// Handle a switch state reported at `now`
+    pub fn update(&mut self, state: SwitchState, now: Instant) -> Vec<GestureEvent> {
+        let mut gestures = self.tick(now);
+
+        if self.press.as_ref().map(|press| &press.direction) == Some(&state) {
+            return gestures;
+        }
+        if let Some(press) = self.press.take() {
+            self.release(press, now, &mut gestures);
+        }
+        if state != SwitchState::None {
+            self.start_press(state, now, &mut gestures);
+        }
+        gestures
+    }
+
+    /// Emit gestures that are due by `now` without a new switch state
+    pub fn tick(&mut self, now: Instant) -> Vec<GestureEvent> {
+        let mut gestures = Vec::new();
+
+        if let Some((direction, released_at)) = &self.pending_click {
+            if now.duration_since(*released_at) >= self.config.double_click {
+                gestures.push(event(direction.clone(), Gesture::Click));
+                self.pending_click = None;
+            }
+        }
+
+        if let Some(press) = self.press.as_mut() {
+            while now >= press.next_repeat {
+                gestures.push(event(press.direction.clone(), Gesture::HoldRepeat));
+                press.next_repeat += self.config.repeat_interval.max(Duration::from_millis(1));
+            }
+        }
+        gestures
+    }
+
+    /// Next instant at which `tick` may emit a gesture
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let click = self
+            .pending_click
+            .as_ref()
+            .map(|(_, released_at)| *released_at + self.config.double_click);
+        let repeat = self.press.as_ref().map(|press| press.next_repeat);
+        click.into_iter().chain(repeat).min()
+    }
+
+    fn start_press(
+        &mut self,
+        direction: SwitchState,
+        now: Instant,
+        gestures: &mut Vec<GestureEvent>,
+    ) {
+        let second = match self.pending_click.take() {
+            Some((pending, _)) if pending == direction => true,
+            Some((pending, _)) => {
+                gestures.push(event(pending, Gesture::Click));
+                false
+            }
+            None => false,
+        };
+        self.press = Some(Press {
+            direction,
+            at: now,
+            second,
+            next_repeat: now + self.config.long_press,
+        });
+    }
+
+    fn release(&mut self, press: Press, now: Instant, gestures: &mut Vec<GestureEvent>) {
+        let held = now.duration_since(press.at);
+        if held >= self.config.long_press {
+            if press.second {
+                gestures.push(event(press.direction.clone(), Gesture::Click));
+            }
+            gestures.push(event(press.direction, Gesture::LongPress(held)));
+        } else if press.second {
+            gestures.push(event(press.direction, Gesture::DoubleClick));
+        } else {
+            self.pending_click = Some((press.direction, now));
+        }
+    }
+}
+
+impl Default for GestureRecognizer {
+    fn default() -> Self {
+        Self::new(GestureConfig::default())
+    }
+}
+
+fn event(direction: SwitchState, gesture: Gesture) -> GestureEvent {
+    GestureEvent { direction, gesture }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockConfig, MockObniz};
+    use crate::switch::SwitchManager;
+    use futures::StreamExt;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn gestures(events: Vec<GestureEvent>) -> Vec<Gesture> {
+        events.into_iter().map(|event| event.gesture).collect()
+    }
+
+    #[test]
+    fn test_click_fires_after_double_click_window() {
+        let start = Instant::now();
+        let mut recognizer = GestureRecognizer::default();
+
+        assert!(recognizer.update(SwitchState::Push, start).is_empty());
+        assert!(recognizer
+            .update(SwitchState::None, start + ms(100))
+            .is_empty());
+        assert_eq!(recognizer.next_deadline(), Some(start + ms(400)));
+        assert!(recognizer.tick(start + ms(350)).is_empty());
+
+        assert_eq!(
+            recognizer.tick(start + ms(450)),
+            vec![event(SwitchState::Push, Gesture::Click)]
+        );
+        assert_eq!(recognizer.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_double_click() {
+        let start = Instant::now();
+        let mut recognizer = GestureRecognizer::default();
+
+        recognizer.update(SwitchState::Left, start);
+        recognizer.update(SwitchState::None, start + ms(80));
+        assert!(recognizer
+            .update(SwitchState::Left, start + ms(200))
+            .is_empty());
+
+        assert_eq!(
+            recognizer.update(SwitchState::None, start + ms(280)),
+            vec![event(SwitchState::Left, Gesture::DoubleClick)]
+        );
+        assert!(recognizer.tick(start + ms(2000)).is_empty());
+    }
+
+    #[test]
+    fn test_other_direction_completes_pending_click() {
+        let start = Instant::now();
+        let mut recognizer = GestureRecognizer::default();
+
+        recognizer.update(SwitchState::Left, start);
+        recognizer.update(SwitchState::None, start + ms(50));
+
+        assert_eq!(
+            recognizer.update(SwitchState::Right, start + ms(100)),
+            vec![event(SwitchState::Left, Gesture::Click)]
+        );
+    }
+
+    #[test]
+    fn test_long_press_with_hold_repeat() {
+        let start = Instant::now();
+        let config = GestureConfig::new()
+            .long_press(ms(500))
+            .repeat_interval(ms(100));
+        let mut recognizer = GestureRecognizer::new(config);
+
+        recognizer.update(SwitchState::Push, start);
+        assert!(recognizer.tick(start + ms(499)).is_empty());
+        assert_eq!(recognizer.next_deadline(), Some(start + ms(500)));
+        assert_eq!(
+            gestures(recognizer.tick(start + ms(650))),
+            vec![Gesture::HoldRepeat, Gesture::HoldRepeat]
+        );
+
+        assert_eq!(
+            gestures(recognizer.update(SwitchState::None, start + ms(720))),
+            vec![Gesture::HoldRepeat, Gesture::LongPress(ms(720))]
+        );
+        assert_eq!(recognizer.next_deadline(), None);
+    }
+
+    #[tokio::test]
+    async fn test_switch_gestures_stream() {
+        let mock = MockObniz::new(MockConfig::default());
+        let config = GestureConfig::new().double_click(Duration::from_millis(30));
+        let mut gestures = SwitchManager::new(mock.clone())
+            .gestures(config)
+            .await
+            .unwrap();
+
+        mock.server()
+            .press_switch(&[("left", "left"), ("none", "release")])
+            .await;
+        let gesture = tokio::time::timeout(Duration::from_secs(1), gestures.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(gesture.direction, SwitchState::Left);
+        assert_eq!(gesture.gesture, Gesture::Click);
+
+        // Dropping the stream stops recognition and unregisters its callback
+        drop(gestures);
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(mock.server().subscribers("switch"), 0);
+    }
+}
@@ -0,0 +1,283 @@
+//! `embedded-hal` 1.0, `embedded-hal-async` and `embedded-io-async` implementations,
+//! so drivers written against those traits run over an obniz.
+//!
+//! The blocking traits (`InputPin`, `SetDutyCycle`) are called from inside a tokio
+//! runtime. Calls that need a device round-trip (`InputPin::is_high`) block the
+//! current worker and therefore need the multi-threaded runtime.
+
+use std::future::Future;
+
+use embedded_hal::digital::{self, InputPin, OutputPin};
+use embedded_hal::pwm::{self, SetDutyCycle};
+use embedded_hal_async::digital::Wait;
+use futures::StreamExt;
+use serde_json::json;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::error::{ObnizError, ObnizResult};
+use crate::io::IoPin;
+use crate::pwm::PwmChannel;
+use crate::transport::ObnizTransport;
+use crate::uart::UartChannel;
+
+impl digital::Error for ObnizError {
+    fn kind(&self) -> digital::ErrorKind {
+        digital::ErrorKind::Other
+    }
+}
+
+impl pwm::Error for ObnizError {
+    fn kind(&self) -> pwm::ErrorKind {
+        pwm::ErrorKind::Other
+    }
+}
+
+impl embedded_io::Error for ObnizError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            ObnizError::Timeout => embedded_io::ErrorKind::TimedOut,
+            ObnizError::Connection(_) | ObnizError::WebSocket(_) => {
+                embedded_io::ErrorKind::NotConnected
+            }
+            ObnizError::PermissionDenied => embedded_io::ErrorKind::PermissionDenied,
+            ObnizError::InvalidPin(_)
+            | ObnizError::UnsupportedPin { .. }
+            | ObnizError::UnsupportedFeature { .. } => embedded_io::ErrorKind::InvalidInput,
+            _ => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+/// Run `future` to completion from a blocking trait method
+fn block_on<F: Future>(future: F) -> ObnizResult<F::Output> {
+    let handle = Handle::try_current().map_err(|_| {
+        ObnizError::Generic("Blocking embedded-hal calls need a tokio runtime".to_string())
+    })?;
+    if handle.runtime_flavor() != RuntimeFlavor::MultiThread {
+        return Err(ObnizError::Generic(
+            "Blocking embedded-hal calls need the multi-threaded tokio runtime".to_string(),
+        ));
+    }
+    Ok(tokio::task::block_in_place(|| handle.block_on(future)))
+}
+
+fn stream_ended(pin: u8) -> ObnizError {
+    ObnizError::Connection(format!(
+        "IO{pin} stopped streaming before the wait finished"
+    ))
+}
+
+impl<T: ObnizTransport> IoPin<T> {
+    /// Resolve once the pin reads `level`
+    async fn wait_for_level(&self, level: bool) -> ObnizResult<()> {
+        // Stream before reading so a change right after the `get` is not missed
+        let mut changes = self.changes().await?;
+        if self.get().await? == level {
+            return Ok(());
+        }
+        while let Some(value) = changes.next().await {
+            if value == level {
+                return Ok(());
+            }
+        }
+        Err(stream_ended(self.pin()))
+    }
+
+    /// Resolve on the next change, or the next change to `to` if given
+    async fn wait_for_edge(&self, to: Option<bool>) -> ObnizResult<()> {
+        let mut changes = self.changes().await?;
+        let mut last = self.get().await?;
+        while let Some(value) = changes.next().await {
+            if value != last && to.is_none_or(|to| value == to) {
+                return Ok(());
+            }
+            last = value;
+        }
+        Err(stream_ended(self.pin()))
+    }
+}
+
+impl<T: ObnizTransport> digital::ErrorType for IoPin<T> {
+    type Error = ObnizError;
+}
+
+impl<T: ObnizTransport> OutputPin for IoPin<T> {
+    fn set_low(&mut self) -> ObnizResult<()> {
+        self.send_claimed(json!([self.set_command(false)?]))
+    }
+
+    fn set_high(&mut self) -> ObnizResult<()> {
+        self.send_claimed(json!([self.set_command(true)?]))
+    }
+}
+
+impl<T: ObnizTransport> InputPin for IoPin<T> {
+    fn is_high(&mut self) -> ObnizResult<bool> {
+        block_on(self.get())?
+    }
+
+    fn is_low(&mut self) -> ObnizResult<bool> {
+        Ok(!self.is_high()?)
+    }
+}
+
+impl<T: ObnizTransport> Wait for IoPin<T> {
+    async fn wait_for_high(&mut self) -> ObnizResult<()> {
+        self.wait_for_level(true).await
+    }
+
+    async fn wait_for_low(&mut self) -> ObnizResult<()> {
+        self.wait_for_level(false).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> ObnizResult<()> {
+        self.wait_for_edge(Some(true)).await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> ObnizResult<()> {
+        self.wait_for_edge(Some(false)).await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> ObnizResult<()> {
+        self.wait_for_edge(None).await
+    }
+}
+
+impl<T: ObnizTransport> pwm::ErrorType for PwmChannel<T> {
+    type Error = ObnizError;
+}
+
+/// Duty cycle relative to the frequency last set on this handle
+impl<T: ObnizTransport> SetDutyCycle for PwmChannel<T> {
+    fn max_duty_cycle(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> ObnizResult<()> {
+        let frequency = self.frequency().ok_or_else(|| {
+            ObnizError::Generic("Set the PWM frequency before the duty cycle".to_string())
+        })?;
+        let period_ms = 1000.0 / frequency as f64;
+        let pulse_width_ms = period_ms * duty as f64 / u16::MAX as f64;
+
+        let request = json!([self.pulse_width_command(pulse_width_ms)?]);
+        self.transport()
+            .send_message(Message::from(request.to_string()))
+    }
+}
+
+impl<T: ObnizTransport> embedded_io::ErrorType for UartChannel<T> {
+    type Error = ObnizError;
+}
+
+/// Reading is cancel-safe: bytes are only taken once `read` returns
+impl<T: ObnizTransport> embedded_io_async::Read for UartChannel<T> {
+    async fn read(&mut self, buf: &mut [u8]) -> ObnizResult<usize> {
+        UartChannel::read(self, buf).await
+    }
+}
+
+impl<T: ObnizTransport> embedded_io_async::Write for UartChannel<T> {
+    async fn write(&mut self, buf: &[u8]) -> ObnizResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.send(buf.to_vec()).await?;
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> ObnizResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::IoManager;
+    use crate::mock::{MockConfig, MockObniz};
+    use crate::pwm::PwmManager;
+    use crate::uart::UartManager;
+    use embedded_io_async::{Read, Write};
+
+    #[tokio::test]
+    async fn test_digital_output_and_wait() {
+        let mock = MockObniz::new(MockConfig::default());
+        let mut pin = IoManager::new(mock.clone()).pin(1).unwrap();
+        pin.set_high().unwrap();
+        pin.set_low().unwrap();
+        assert_eq!(mock.resources().owner(1), Some("io1".to_string()));
+
+        let server = mock.server();
+        let trigger = tokio::spawn(async move {
+            while server.subscribers("io1") == 0 {
+                tokio::task::yield_now().await;
+            }
+            for value in [false, true] {
+                server.trigger_callback("io1", json!({"io1": value})).await;
+            }
+        });
+        pin.wait_for_rising_edge().await.unwrap();
+        trigger.await.unwrap();
+
+        // The mock reads pins as low
+        pin.wait_for_low().await.unwrap();
+        assert!(matches!(pin.is_high(), Err(ObnizError::Generic(_))));
+        assert_eq!(
+            mock.server().get_sent_messages()[..2],
+            [json!([{"io1": true}]), json!([{"io1": false}])]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_blocking_input_on_multi_thread_runtime() {
+        let mock = MockObniz::new(MockConfig::default());
+        let mut pin = IoManager::new(mock).pin(2).unwrap();
+        assert!(pin.is_low().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_pwm_duty_cycle() {
+        let mock = MockObniz::new(MockConfig::default());
+        let mut pwm = PwmManager::new(mock.clone()).allocate(3).await.unwrap();
+        assert!(pwm.set_duty_cycle_percent(50).is_err());
+
+        pwm.set_frequency(1000).await.unwrap();
+        pwm.set_duty_cycle_fully_on().unwrap();
+        SetDutyCycle::set_duty_cycle(&mut *pwm, 0).unwrap();
+
+        let sent = mock.server().get_sent_messages();
+        assert_eq!(sent[2], json!([{"pwm0": {"pulse": 1.0}}]));
+        assert_eq!(sent[3], json!([{"pwm0": {"pulse": 0.0}}]));
+    }
+
+    #[tokio::test]
+    async fn test_uart_read_and_write() {
+        let mock = MockObniz::new(MockConfig::default());
+        let mut uart = UartManager::new(mock.clone()).channel(0).unwrap();
+        uart.write_all(b"hi").await.unwrap();
+
+        let server = mock.server();
+        let trigger = tokio::spawn(async move {
+            while server.subscribers("uart0") == 0 {
+                tokio::task::yield_now().await;
+            }
+            server
+                .trigger_callback("uart0", json!({"uart0": {"data": [1, 2, 3]}}))
+                .await;
+        });
+
+        let mut buf = [0u8; 2];
+        assert_eq!(Read::read(&mut uart, &mut buf).await.unwrap(), 2);
+        assert_eq!(buf, [1, 2]);
+        trigger.await.unwrap();
+        assert_eq!(Read::read(&mut uart, &mut buf).await.unwrap(), 1);
+        assert_eq!(buf[0], 3);
+
+        assert_eq!(
+            mock.server().get_sent_messages()[0],
+            json!([{"uart0": {"data": [104, 105]}}])
+        );
+    }
+}
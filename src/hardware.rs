@@ -0,0 +1,297 @@
+use crate::error::{ObnizError, ObnizResult};
+
+/// Pins, peripherals and screen of one kind of obniz board
+#[derive(Debug, Clone, PartialEq)]
+pub struct HardwareProfile {
+    /// `hw` string reported in `ws.ready`, e.g. `obnizb1`
+    pub hw: String,
+    /// Usable IO pin numbers
+    pub io_pins: Vec<u8>,
+    /// IO pins that can read voltages
+    pub ad_channels: Vec<u8>,
+    /// Number of PWM channels (`pwm0` ..)
+    pub pwm_channels: u8,
+    /// Number of UART channels (`uart0` ..)
+    pub uart_channels: u8,
+    /// Screen size in pixels, None for boards without a screen
+    pub display: Option<(u16, u16)>,
+}
+
+impl Default for HardwareProfile {
+    fn default() -> Self {
+        Self::obniz_board()
+    }
+}
+
+impl HardwareProfile {
+    /// obniz Board (`obnizb1`)
+    pub fn obniz_board() -> Self {
+        Self {
+            hw: "obnizb1".to_string(),
+            io_pins: (0..=11).collect(),
+            ad_channels: (0..=11).collect(),
+            pwm_channels: 6,
+            uart_channels: 3,
+            display: Some((128, 64)),
+        }
+    }
+
+    /// obniz Board 1Y (`obnizb2`), same IO set as the obniz Board
+    pub fn obniz_board_1y() -> Self {
+        Self {
+            hw: "obnizb2".to_string(),
+            ..Self::obniz_board()
+        }
+    }
+
+    /// M5StickC running obnizOS (`m5stickc`)
+    pub fn m5stickc() -> Self {
+        Self {
+            hw: "m5stickc".to_string(),
+            io_pins: vec![0, 9, 10, 26, 32, 33, 36, 37, 39],
+            ad_channels: vec![0, 26, 32, 33, 36],
+            pwm_channels: 6,
+            uart_channels: 2,
+            display: Some((80, 160)),
+        }
+    }
+
+    /// M5Stack Basic running obnizOS (`m5stack_basic`)
+    pub fn m5stack() -> Self {
+        Self {
+            hw: "m5stack_basic".to_string(),
+            io_pins: vec![
+                0, 1, 2, 3, 5, 12, 13, 15, 16, 17, 18, 19, 21, 22, 23, 25, 26, 34, 35, 36,
+            ],
+            ad_channels: vec![2, 12, 13, 15, 25, 26, 34, 35, 36],
+            pwm_channels: 6,
+            uart_channels: 2,
+            display: Some((320, 240)),
+        }
+    }
+
+    /// Bare ESP32 board running obnizOS (`esp32w`, `esp32p`), no screen
+    pub fn esp32(hw: &str) -> Self {
+        let mut io_pins = vec![0, 2, 4, 5, 12, 13, 14, 15, 16, 17, 18, 19, 21, 22, 23];
+        io_pins.extend([25, 26, 27, 32, 33, 34, 35, 36, 37, 38, 39]);
+        Self {
+            hw: hw.to_string(),
+            io_pins,
+            ad_channels: vec![0, 2, 4, 12, 13, 14, 15, 25, 26, 27, 32, 33, 34, 35, 36, 39],
+            pwm_channels: 6,
+            uart_channels: 2,
+            display: None,
+        }
+    }
+
+    /// Profile for a `hw` string; unknown boards are treated as an obniz Board
+    pub fn from_hw(hw: &str) -> Self {
+        match hw {
+            "obnizb1" => Self::obniz_board(),
+            "obnizb2" => Self::obniz_board_1y(),
+            "m5stickc" => Self::m5stickc(),
+            "m5stack_basic" => Self::m5stack(),
+            "esp32w" | "esp32p" => Self::esp32(hw),
+            _ => Self {
+                hw: hw.to_string(),
+                ..Self::obniz_board()
+            },
+        }
+    }
+
+    /// Check that `pin` exists on the board
+    pub fn check_io(&self, pin: u8) -> ObnizResult<()> {
+        if self.io_pins.contains(&pin) {
+            Ok(())
+        } else {
+            Err(self.pin_error(pin))
+        }
+    }
+
+    /// Check that `pin` can be used as an AD channel
+    pub fn check_ad(&self, pin: u8) -> ObnizResult<()> {
+        if self.ad_channels.contains(&pin) {
+            Ok(())
+        } else if self.io_pins.contains(&pin) {
+            Err(ObnizError::UnsupportedFeature {
+                feature: format!("AD on io{pin}"),
+                hardware: self.hw.clone(),
+            })
+        } else {
+            Err(self.pin_error(pin))
+        }
+    }
+
+    /// Check that PWM `channel` exists on the board
+    pub fn check_pwm(&self, channel: u8) -> ObnizResult<()> {
+        self.check_channel("PWM", channel, self.pwm_channels)
+    }
+
+    /// Check that UART `channel` exists on the board
+    pub fn check_uart(&self, channel: u8) -> ObnizResult<()> {
+        self.check_channel("UART", channel, self.uart_channels)
+    }
+
+    /// Check that the board has a screen
+    pub fn check_display(&self) -> ObnizResult<()> {
+        match self.display {
+            Some(_) => Ok(()),
+            None => Err(ObnizError::UnsupportedFeature {
+                feature: "display".to_string(),
+                hardware: self.hw.clone(),
+            }),
+        }
+    }
+
+    fn check_channel(&self, module: &str, channel: u8, count: u8) -> ObnizResult<()> {
+        match count {
+            0 => Err(ObnizError::UnsupportedFeature {
+                feature: module.to_string(),
+                hardware: self.hw.clone(),
+            }),
+            _ if channel >= count => Err(ObnizError::Generic(format!(
+                "{module} channel must be 0-{}",
+                count - 1
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// `InvalidPin` describes the 0-11 numbering of the obniz Board, so other
+    /// boards report `UnsupportedPin` instead
+    fn pin_error(&self, pin: u8) -> ObnizError {
+        if self.io_pins.iter().copied().eq(0..=11) {
+            ObnizError::InvalidPin(pin)
+        } else {
+            ObnizError::UnsupportedPin {
+                pin,
+                hardware: self.hw.clone(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ad::AdManager;
+    use crate::display::{DisplayManager, PinAssignment};
+    use crate::io::IoManager;
+    use crate::mock::{MockConfig, MockObniz};
+    use crate::pwm::PwmManager;
+    use crate::uart::UartManager;
+    use serde_json::json;
+
+    #[test]
+    fn test_profile_from_hw() {
+        assert_eq!(
+            HardwareProfile::from_hw("obnizb1"),
+            HardwareProfile::default()
+        );
+        assert_eq!(HardwareProfile::from_hw("obnizb2").hw, "obnizb2");
+        assert_eq!(HardwareProfile::from_hw("esp32w").display, None);
+
+        let unknown = HardwareProfile::from_hw("future_board");
+        assert_eq!(unknown.hw, "future_board");
+        assert_eq!(unknown.io_pins, HardwareProfile::obniz_board().io_pins);
+    }
+
+    #[test]
+    fn test_obniz_board_validation() {
+        let board = HardwareProfile::obniz_board();
+        assert!(board.check_io(11).is_ok());
+        assert!(matches!(
+            board.check_io(12),
+            Err(ObnizError::InvalidPin(12))
+        ));
+        assert!(board.check_ad(0).is_ok());
+        assert!(board.check_pwm(5).is_ok());
+        assert!(matches!(board.check_pwm(6), Err(ObnizError::Generic(_))));
+        assert!(board.check_uart(2).is_ok());
+        assert!(board.check_display().is_ok());
+    }
+
+    #[test]
+    fn test_other_board_validation() {
+        let stick = HardwareProfile::m5stickc();
+        assert!(stick.check_io(26).is_ok());
+        assert!(matches!(
+            stick.check_io(1),
+            Err(ObnizError::UnsupportedPin { pin: 1, .. })
+        ));
+        assert!(matches!(
+            stick.check_ad(37),
+            Err(ObnizError::UnsupportedFeature { .. })
+        ));
+        assert!(stick.check_uart(2).is_err());
+
+        let esp32 = HardwareProfile::esp32("esp32w");
+        assert!(matches!(
+            esp32.check_display(),
+            Err(ObnizError::UnsupportedFeature { feature, hardware })
+                if feature == "display" && hardware == "esp32w"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_managers_validate_against_hardware_profile() {
+        let mock = MockObniz::new(MockConfig {
+            hardware: HardwareProfile::esp32("esp32w"),
+            ..Default::default()
+        });
+
+        IoManager::new(mock.clone())
+            .set_pin(25, true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            IoManager::new(mock.clone()).set_pin(6, true).await,
+            Err(ObnizError::UnsupportedPin { pin: 6, .. })
+        ));
+        assert!(matches!(
+            DisplayManager::new(mock.clone()).text("hi").await,
+            Err(ObnizError::UnsupportedFeature { .. })
+        ));
+        let assignment = PinAssignment {
+            pin: 25,
+            module_name: "sensor".to_string(),
+            pin_name: "vcc".to_string(),
+        };
+        assert!(matches!(
+            DisplayManager::new(mock.clone())
+                .pin_assign(vec![assignment])
+                .await,
+            Err(ObnizError::UnsupportedFeature { .. })
+        ));
+        assert!(UartManager::new(mock.clone()).channel(2).is_err());
+        assert_eq!(
+            mock.server().get_sent_messages(),
+            vec![json!([{"io25": true}])]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deinit_all_covers_the_board_channels() {
+        let mock = MockObniz::new(MockConfig {
+            hardware: HardwareProfile::m5stickc(),
+            ..Default::default()
+        });
+        let sent_keys = |mock: &MockObniz| -> Vec<String> {
+            mock.server()
+                .get_sent_messages()
+                .iter()
+                .flat_map(|frame| frame[0].as_object().unwrap().keys().cloned())
+                .collect()
+        };
+
+        AdManager::new(mock.clone()).deinit_all().await.unwrap();
+        assert_eq!(sent_keys(&mock), ["ad0", "ad26", "ad32", "ad33", "ad36"]);
+
+        mock.server().clear_sent_messages();
+        PwmManager::new(mock.clone()).deinit_all().await.unwrap();
+        assert_eq!(
+            sent_keys(&mock),
+            ["pwm0", "pwm1", "pwm2", "pwm3", "pwm4", "pwm5"]
+        );
+    }
+}
@@ -1,9 +1,14 @@
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use tokio_tungstenite::tungstenite::protocol::Message;
 
-use crate::error::{validate_pin, ObnizError, ObnizResult};
+use crate::callbacks::Subscription;
+use crate::error::{ObnizError, ObnizResult};
 use crate::obniz::Obniz;
+use crate::resources::PinClaim;
+use crate::response::ObnizResponse;
+use crate::stream::{InputStream, StreamOptions, StreamSink};
+use crate::transport::ObnizTransport;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -39,76 +44,74 @@ pub struct IoConfig {
 }
 
 #[derive(Debug)]
-pub struct IoPin {
+pub struct IoPin<T: ObnizTransport = Obniz> {
     pin: u8,
-    obniz: Obniz,
+    obniz: T,
 }
 
-impl IoPin {
-    pub fn new(pin: u8, obniz: Obniz) -> Self {
+impl<T: ObnizTransport> IoPin<T> {
+    pub fn new(pin: u8, obniz: T) -> Self {
         Self { pin, obniz }
     }
 
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
     pub fn pin_key(&self) -> String {
         format!("io{}", self.pin)
     }
 
+    /// Validate the pin; the claim taking it for this IO pin until `deinit`
+    pub(crate) fn pin_claim(&self) -> ObnizResult<PinClaim> {
+        self.obniz.hardware_profile().check_io(self.pin)?;
+        Ok(PinClaim::new(&self.pin_key(), &[self.pin]))
+    }
+
+    /// Send `request`, taking the pin once it goes out
+    pub(crate) fn send_claimed(&self, request: Value) -> ObnizResult<()> {
+        let claim = self.pin_claim()?;
+        self.obniz.resources().with_claims(&[claim], || {
+            self.obniz.send_message(Message::from(request.to_string()))
+        })
+    }
+
     /// Get the current state of the pin
     pub async fn get(&self) -> ObnizResult<bool> {
-        validate_pin(self.pin)?;
+        let claimed = self.obniz.resources().take(&[self.pin_claim()?])?;
 
         let pin_key = self.pin_key();
         let request = json!([{&pin_key: "get"}]);
         let message = Message::from(request.to_string());
 
-        let response = self
-            .obniz
-            .send_await_response(message, pin_key.clone())
-            .await
-            .map_err(|e| ObnizError::Connection(e.to_string()))?;
-
-        // Parse the response to extract the boolean value
-        // Response format is typically [{"io1": false}]
-        if let Some(array) = response.as_array() {
-            if let Some(first_item) = array.first() {
-                if let Some(value) = first_item.get(&pin_key) {
-                    return value.as_bool().ok_or_else(|| {
-                        ObnizError::IoOperation("Invalid response format".to_string())
-                    });
-                }
+        let response = match self.obniz.request(message, pin_key.clone()).await {
+            Ok(response) => response,
+            Err(e) => {
+                claimed.rollback();
+                return Err(e);
             }
+        };
+        match response {
+            ObnizResponse::Io { value, .. } => Ok(value),
+            other => Err(other.unexpected(&pin_key)),
         }
+    }
 
-        // Fallback: try direct object access
-        if let Some(value) = response.get(&pin_key) {
-            value
-                .as_bool()
-                .ok_or_else(|| ObnizError::IoOperation("Invalid response format".to_string()))
-        } else {
-            Err(ObnizError::IoOperation(format!(
-                "No response for pin {}",
-                self.pin
-            )))
-        }
+    /// Command object for `set`, e.g. `{"io0": true}`; the pin is checked, not yet taken
+    pub fn set_command(&self, value: bool) -> ObnizResult<Value> {
+        self.obniz.resources().check(&[self.pin_claim()?])?;
+        Ok(json!({self.pin_key(): value}))
     }
 
     /// Set the pin to a specific value
     pub async fn set(&self, value: bool) -> ObnizResult<()> {
-        validate_pin(self.pin)?;
-
-        let pin_key = self.pin_key();
-        let request = json!([{&pin_key: value}]);
-        let message = Message::from(request.to_string());
-
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))?;
-        Ok(())
+        self.send_claimed(json!([self.set_command(value)?]))
     }
 
-    /// Configure the pin with detailed settings
-    pub async fn configure(&self, config: IoConfig) -> ObnizResult<()> {
-        validate_pin(self.pin)?;
+    /// Command object for `configure`, e.g. `{"io0": {"direction": "output", "value": true}}`;
+    /// the pin is checked, not yet taken
+    pub fn configure_command(&self, config: IoConfig) -> ObnizResult<Value> {
+        self.obniz.resources().check(&[self.pin_claim()?])?;
         let pin_key = self.pin_key();
         let mut pin_config = json!({
             "direction": config.direction
@@ -130,18 +133,16 @@ impl IoPin {
             pin_config["stream"] = json!(stream);
         }
 
-        let request = json!([{&pin_key: pin_config}]);
-        let message = Message::from(request.to_string());
+        Ok(json!({pin_key: pin_config}))
+    }
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))?;
-        Ok(())
+    /// Configure the pin with detailed settings
+    pub async fn configure(&self, config: IoConfig) -> ObnizResult<()> {
+        self.send_claimed(json!([self.configure_command(config)?]))
     }
 
     /// Set the pin as input with optional stream mode
     pub async fn set_as_input(&self, enable_stream: bool) -> ObnizResult<()> {
-        validate_pin(self.pin)?;
         let config = IoConfig {
             direction: Direction::Input,
             value: None,
@@ -154,7 +155,6 @@ impl IoPin {
 
     /// Set the pin as output with a specific value
     pub async fn set_as_output(&self, value: bool) -> ObnizResult<()> {
-        validate_pin(self.pin)?;
         let config = IoConfig {
             direction: Direction::Output,
             value: Some(value),
@@ -167,55 +167,44 @@ impl IoPin {
 
     /// Set the output type of the pin
     pub async fn set_output_type(&self, output_type: OutputType) -> ObnizResult<()> {
-        validate_pin(self.pin)?;
         let pin_key = self.pin_key();
-        let request = json!([{&pin_key: {"output_type": output_type}}]);
-        let message = Message::from(request.to_string());
-
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))?;
-        Ok(())
+        self.send_claimed(json!([{&pin_key: {"output_type": output_type}}]))
     }
 
     /// Set the pull type of the pin
     pub async fn set_pull_type(&self, pull_type: PullType) -> ObnizResult<()> {
-        validate_pin(self.pin)?;
         let pin_key = self.pin_key();
-        let request = json!([{&pin_key: {"pull_type": pull_type}}]);
-        let message = Message::from(request.to_string());
-
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))?;
-        Ok(())
+        self.send_claimed(json!([{&pin_key: {"pull_type": pull_type}}]))
     }
 
     /// Register a callback for this pin's state changes (stream mode)
-    /// This will automatically enable stream mode for the pin
-    pub async fn on_change<F>(&self, callback: F) -> ObnizResult<()>
+    /// This will automatically enable stream mode for the pin.
+    /// The callback stays registered until the returned subscription is dropped.
+    pub async fn on_change<F>(&self, callback: F) -> ObnizResult<Subscription>
     where
         F: Fn(bool) + Send + Sync + 'static,
     {
-        validate_pin(self.pin)?;
         // First, enable stream mode for this pin
         self.set_as_input(true).await?;
 
-        let pin_key = self.pin_key();
-        let pin_key_clone = pin_key.clone();
-
-        self.obniz
-            .register_callback(pin_key, move |response| {
-                // Parse the response to extract the pin value
-                if let Some(value) = response.get(&pin_key_clone) {
-                    if let Some(bool_value) = value.as_bool() {
-                        callback(bool_value);
-                    }
-                }
-            })
-            .map_err(|e| ObnizError::CallbackError(e.to_string()))?;
+        self.obniz.subscribe(self.pin_key(), move |response| {
+            if let ObnizResponse::Io { value, .. } = response {
+                callback(value);
+            }
+        })
+    }
 
-        Ok(())
+    /// Pin states as a stream (enables stream mode); dropping it unregisters the callback
+    pub async fn changes(&self) -> ObnizResult<InputStream<bool>> {
+        self.changes_with(StreamOptions::default()).await
+    }
+
+    /// `changes` with custom buffering
+    pub async fn changes_with(&self, options: StreamOptions) -> ObnizResult<InputStream<bool>> {
+        let sink = StreamSink::new(options);
+        let writer = sink.clone();
+        let subscription = self.on_change(move |value| writer.push(value)).await?;
+        Ok(sink.into_stream(subscription))
     }
 
     /// Enable stream mode for this pin without setting up a callback
@@ -228,46 +217,196 @@ impl IoPin {
         self.set_as_input(false).await
     }
 
-    /// Remove the callback for this pin
+    /// Remove every callback for this pin
     pub fn remove_callback(&self) -> ObnizResult<()> {
-        validate_pin(self.pin)?;
+        self.obniz.hardware_profile().check_io(self.pin)?;
         let pin_key = self.pin_key();
-        self.obniz
-            .unregister_callback(pin_key)
-            .map_err(|e| ObnizError::CallbackError(e.to_string()))
+        self.obniz.unregister_callback(pin_key)
     }
 
     /// Deinitialize the pin
     pub async fn deinit(&self) -> ObnizResult<()> {
-        validate_pin(self.pin)?;
+        // Validates the pin; nothing is taken since the pin is released below
+        self.pin_claim()?;
         let pin_key = self.pin_key();
         let request = json!([{&pin_key: null}]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))?;
+        self.obniz.send_message(message)?;
+        self.obniz.resources().release(&pin_key);
+        Ok(())
+    }
+}
+
+/// Pins driven and read together as one value, `pins[0]` being bit 0.
+/// Writes and reads each go out as a single frame, so outputs change together.
+#[derive(Debug)]
+pub struct IoBus<T: ObnizTransport = Obniz> {
+    pins: Vec<IoPin<T>>,
+    obniz: T,
+}
+
+impl<T: ObnizTransport> IoBus<T> {
+    /// Group 1 to 8 distinct pins into a bus
+    pub fn new(pins: &[u8], obniz: T) -> ObnizResult<Self> {
+        if pins.is_empty() || pins.len() > 8 {
+            return Err(ObnizError::Generic(
+                "IO bus must have 1 to 8 pins".to_string(),
+            ));
+        }
+        let hardware = obniz.hardware_profile();
+        for (index, pin) in pins.iter().enumerate() {
+            hardware.check_io(*pin)?;
+            if pins[..index].contains(pin) {
+                return Err(ObnizError::Generic(format!(
+                    "Pin {pin} appears twice in the IO bus"
+                )));
+            }
+        }
+
+        Ok(Self {
+            pins: pins
+                .iter()
+                .map(|pin| IoPin::new(*pin, obniz.clone()))
+                .collect(),
+            obniz,
+        })
+    }
+
+    /// Bus pins, least significant bit first
+    pub fn pins(&self) -> Vec<u8> {
+        self.pins.iter().map(|pin| pin.pin).collect()
+    }
+
+    pub fn width(&self) -> usize {
+        self.pins.len()
+    }
+
+    fn check_value(&self, value: u8) -> ObnizResult<()> {
+        if self.width() < 8 && value >> self.width() != 0 {
+            return Err(ObnizError::Generic(format!(
+                "Value {value} does not fit in a {}-bit IO bus",
+                self.width()
+            )));
+        }
         Ok(())
     }
+
+    fn claims(&self) -> ObnizResult<Vec<PinClaim>> {
+        self.pins.iter().map(IoPin::pin_claim).collect()
+    }
+
+    /// Send `request`, taking every bus pin once it goes out
+    fn send_claimed(&self, request: Vec<Value>) -> ObnizResult<()> {
+        self.obniz.resources().with_claims(&self.claims()?, || {
+            self.obniz
+                .send_message(Message::from(Value::Array(request).to_string()))
+        })
+    }
+
+    /// Command objects for `write`, one per pin; the pins are checked, not yet taken
+    pub fn write_commands(&self, value: u8) -> ObnizResult<Vec<Value>> {
+        self.check_value(value)?;
+        self.pins
+            .iter()
+            .enumerate()
+            .map(|(bit, pin)| pin.set_command(value & (1 << bit) != 0))
+            .collect()
+    }
+
+    /// Set every pin from the bits of `value` in one frame
+    pub async fn write(&self, value: u8) -> ObnizResult<()> {
+        let request = self.write_commands(value)?;
+        self.send_claimed(request)
+    }
+
+    /// Read every pin with one frame of `get`s and combine the states into a value
+    pub async fn read(&self) -> ObnizResult<u8> {
+        let claimed = self.obniz.resources().take(&self.claims()?)?;
+        let keys: Vec<String> = self.pins.iter().map(IoPin::pin_key).collect();
+        let request: Vec<Value> = keys.iter().map(|key| json!({key: "get"})).collect();
+
+        let message = Message::from(Value::Array(request).to_string());
+        let responses = match self.obniz.request_all(message, keys.clone()).await {
+            Ok(responses) => responses,
+            Err(e) => {
+                claimed.rollback();
+                return Err(e);
+            }
+        };
+
+        let mut value = 0u8;
+        for (bit, (response, key)) in responses.into_iter().zip(&keys).enumerate() {
+            match response {
+                ObnizResponse::Io { value: true, .. } => value |= 1 << bit,
+                ObnizResponse::Io { .. } => {}
+                other => return Err(other.unexpected(key)),
+            }
+        }
+        Ok(value)
+    }
+
+    /// Configure every pin as an output starting at `value`, in one frame
+    pub async fn set_as_output(&self, value: u8) -> ObnizResult<()> {
+        self.check_value(value)?;
+        let request = self
+            .pins
+            .iter()
+            .enumerate()
+            .map(|(bit, pin)| {
+                pin.configure_command(IoConfig {
+                    direction: Direction::Output,
+                    value: Some(value & (1 << bit) != 0),
+                    output_type: None,
+                    pull_type: None,
+                    stream: None,
+                })
+            })
+            .collect::<ObnizResult<Vec<_>>>()?;
+        self.send_claimed(request)
+    }
+
+    /// Configure every pin as an input, in one frame
+    pub async fn set_as_input(&self) -> ObnizResult<()> {
+        let request = self
+            .pins
+            .iter()
+            .map(|pin| {
+                pin.configure_command(IoConfig {
+                    direction: Direction::Input,
+                    value: None,
+                    output_type: None,
+                    pull_type: None,
+                    stream: Some(false),
+                })
+            })
+            .collect::<ObnizResult<Vec<_>>>()?;
+        self.send_claimed(request)
+    }
 }
 
 /// IO Manager for handling multiple pins
 #[derive(Debug)]
-pub struct IoManager {
-    obniz: Obniz,
+pub struct IoManager<T: ObnizTransport = Obniz> {
+    obniz: T,
 }
 
-impl IoManager {
-    pub fn new(obniz: Obniz) -> Self {
+impl<T: ObnizTransport> IoManager<T> {
+    pub fn new(obniz: T) -> Self {
         Self { obniz }
     }
 
-    /// Get a specific pin (0-11)
-    pub fn pin(&self, pin: u8) -> ObnizResult<IoPin> {
-        validate_pin(pin)?;
+    /// Get a specific pin, checked against the board's `io_pins`
+    pub fn pin(&self, pin: u8) -> ObnizResult<IoPin<T>> {
+        self.obniz.hardware_profile().check_io(pin)?;
         Ok(IoPin::new(pin, self.obniz.clone()))
     }
 
+    /// Group pins into a bus written and read as one value, `pins[0]` being bit 0
+    pub fn bus(&self, pins: &[u8]) -> ObnizResult<IoBus<T>> {
+        IoBus::new(pins, self.obniz.clone())
+    }
+
     /// Get the current state of a pin
     pub async fn get_pin(&self, pin: u8) -> ObnizResult<bool> {
         self.pin(pin)?.get().await
@@ -295,14 +434,14 @@ impl IoManager {
 
     /// Register a callback for a pin's state changes (stream mode)
     /// This will automatically enable stream mode for the pin
-    pub async fn set_pin_callback<F>(&self, pin: u8, callback: F) -> ObnizResult<()>
+    pub async fn set_pin_callback<F>(&self, pin: u8, callback: F) -> ObnizResult<Subscription>
     where
         F: Fn(bool) + Send + Sync + 'static,
     {
         self.pin(pin)?.on_change(callback).await
     }
 
-    /// Remove the callback for a pin
+    /// Remove every callback for a pin
     pub fn remove_pin_callback(&self, pin: u8) -> ObnizResult<()> {
         self.pin(pin)?.remove_callback()
     }
@@ -322,6 +461,7 @@ impl IoManager {
 mod tests {
     use super::*;
     use crate::error::validate_pin;
+    use crate::mock::{responses, MockConfig, MockObniz};
 
     #[test]
     fn test_pin_validation() {
@@ -406,4 +546,33 @@ mod tests {
     //     // or test framework to create a mock Obniz instance
     //     unimplemented!("Mock Obniz creation for testing")
     // }
+
+    #[tokio::test]
+    async fn test_io_bus_uses_one_frame() {
+        let mock = MockObniz::new(MockConfig::default());
+        let server = mock.server();
+        server.add_response("io5", responses::io_pin_state(5, true));
+        server.add_response("io7", responses::io_pin_state(7, true));
+
+        let io = IoManager::new(mock.clone());
+        let bus = io.bus(&[4, 5, 6, 7]).unwrap();
+        bus.set_as_output(0).await.unwrap();
+        bus.write(0b1010).await.unwrap();
+        assert!(matches!(bus.write(0x10).await, Err(ObnizError::Generic(_))));
+        assert_eq!(bus.read().await.unwrap(), 0b1010);
+
+        assert!(io.bus(&[1, 1]).is_err());
+        assert!(io.bus(&[]).is_err());
+
+        let sent = server.get_sent_messages();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(
+            sent[1],
+            json!([{"io4": false}, {"io5": true}, {"io6": false}, {"io7": true}])
+        );
+        assert_eq!(
+            sent[2],
+            json!([{"io4": "get"}, {"io5": "get"}, {"io6": "get"}, {"io7": "get"}])
+        );
+    }
 }
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::error::{ObnizError, ObnizResult};
+use crate::obniz::Obniz;
+use crate::resources::PinClaim;
+use crate::transport::ObnizTransport;
+
+/// One step of an animation: pin values held for `duration_ms`
+pub type AnimationState = (u32, HashMap<u8, bool>);
+
+/// Runs named pin patterns on the device through `io.animation`,
+/// so blinking and stepping keep device-side timing
+#[derive(Debug, Clone)]
+pub struct IoAnimationManager<T: ObnizTransport = Obniz> {
+    obniz: T,
+}
+
+impl<T: ObnizTransport> IoAnimationManager<T> {
+    pub fn new(obniz: T) -> Self {
+        Self { obniz }
+    }
+
+    /// Command object that starts animation `name`; `repeat: None` loops until paused.
+    /// The pins are checked here and claimed for the animation by `start` until `stop`.
+    pub fn start_command(
+        &self,
+        name: &str,
+        states: &[AnimationState],
+        repeat: Option<u32>,
+    ) -> ObnizResult<Value> {
+        let (command, claim) = self.build_start(name, states, repeat)?;
+        self.obniz.resources().check(&[claim])?;
+        Ok(command)
+    }
+
+    /// Validate an animation; its command and the claim on its pins
+    fn build_start(
+        &self,
+        name: &str,
+        states: &[AnimationState],
+        repeat: Option<u32>,
+    ) -> ObnizResult<(Value, PinClaim)> {
+        if name.is_empty() {
+            return Err(ObnizError::Generic(
+                "Animation name cannot be empty".to_string(),
+            ));
+        }
+        if states.is_empty() {
+            return Err(ObnizError::Generic(
+                "Animation needs at least one state".to_string(),
+            ));
+        }
+        if repeat == Some(0) {
+            return Err(ObnizError::Generic(
+                "Animation repeat count must be at least 1".to_string(),
+            ));
+        }
+
+        let hardware = self.obniz.hardware_profile();
+        let mut pins: Vec<u8> = Vec::new();
+        let mut frames = Vec::with_capacity(states.len());
+        for (duration_ms, values) in states {
+            if *duration_ms == 0 {
+                return Err(ObnizError::Generic(
+                    "Animation state duration must be at least 1 ms".to_string(),
+                ));
+            }
+
+            let mut state_pins: Vec<(&u8, &bool)> = values.iter().collect();
+            state_pins.sort_unstable_by_key(|(pin, _)| **pin);
+            let mut state = Vec::with_capacity(state_pins.len());
+            for (pin, value) in state_pins {
+                hardware.check_io(*pin)?;
+                if !pins.contains(pin) {
+                    pins.push(*pin);
+                }
+                state.push(json!({format!("io{pin}"): value}));
+            }
+            frames.push(json!({"duration": duration_ms, "state": state}));
+        }
+
+        let claim = PinClaim::exact(&IoAnimationManager::owner_key(name), &pins);
+        let mut animation = json!({
+            "name": name,
+            "status": "loop",
+            "states": frames
+        });
+        if let Some(repeat) = repeat {
+            animation["repeat"] = json!(repeat);
+        }
+        Ok((json!({"io": {"animation": animation}}), claim))
+    }
+
+    /// Start animation `name`, looping forever or `repeat` times
+    pub async fn start(
+        &self,
+        name: &str,
+        states: &[AnimationState],
+        repeat: Option<u32>,
+    ) -> ObnizResult<()> {
+        let (command, claim) = self.build_start(name, states, repeat)?;
+        let message = Message::from(json!([command]).to_string());
+        self.obniz
+            .resources()
+            .with_claims(&[claim], || self.obniz.send_message(message))
+    }
+
+    /// Pause animation `name`, leaving its pins in their current state
+    pub async fn pause(&self, name: &str) -> ObnizResult<()> {
+        self.send_status(name, "pause")
+    }
+
+    /// Resume a paused animation
+    pub async fn resume(&self, name: &str) -> ObnizResult<()> {
+        self.send_status(name, "resume")
+    }
+
+    /// Pause animation `name` for good and free its pins for other modules
+    pub async fn stop(&self, name: &str) -> ObnizResult<()> {
+        self.send_status(name, "pause")?;
+        self.obniz
+            .resources()
+            .release(&IoAnimationManager::owner_key(name));
+        Ok(())
+    }
+
+    fn send_status(&self, name: &str, status: &str) -> ObnizResult<()> {
+        let request = json!([{"io": {"animation": {"name": name, "status": status}}}]);
+        self.obniz.send_message(Message::from(request.to_string()))
+    }
+}
+
+// Plain helpers sit on the default transport so callers need no type annotation
+impl IoAnimationManager {
+    /// Key the pins of animation `name` are claimed under
+    pub fn owner_key(name: &str) -> String {
+        format!("animation:{name}")
+    }
+
+    /// Toggle `pin` every `interval_ms`
+    pub fn blink(pin: u8, interval_ms: u32) -> Vec<AnimationState> {
+        vec![
+            (interval_ms, HashMap::from([(pin, true)])),
+            (interval_ms, HashMap::from([(pin, false)])),
+        ]
+    }
+
+    /// Drive one pin high at a time, in order, each for `step_ms`
+    pub fn chase(pins: &[u8], step_ms: u32) -> Vec<AnimationState> {
+        pins.iter()
+            .map(|active| {
+                let values = pins.iter().map(|pin| (*pin, pin == active)).collect();
+                (step_ms, values)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::IoManager;
+    use crate::mock::{MockConfig, MockObniz};
+
+    #[test]
+    fn test_blink_states() {
+        let states = IoAnimationManager::blink(3, 250);
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0], (250, HashMap::from([(3, true)])));
+        assert_eq!(states[1], (250, HashMap::from([(3, false)])));
+    }
+
+    #[test]
+    fn test_chase_states() {
+        let states = IoAnimationManager::chase(&[0, 1, 2], 100);
+        assert_eq!(states.len(), 3);
+        assert_eq!(
+            states[1].1,
+            HashMap::from([(0, false), (1, true), (2, false)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_io_animation_lifecycle() {
+        let mock = MockObniz::new(MockConfig::default());
+        let animation = IoAnimationManager::new(mock.clone());
+
+        let states = vec![
+            (500, HashMap::from([(1, false), (0, true)])),
+            (250, HashMap::from([(0, false)])),
+        ];
+        animation.start("blink", &states, None).await.unwrap();
+        animation.pause("blink").await.unwrap();
+        animation.resume("blink").await.unwrap();
+
+        // The animation owns its pins until it stops
+        assert_eq!(
+            mock.resources()
+                .pins(&IoAnimationManager::owner_key("blink")),
+            vec![0, 1]
+        );
+        assert!(matches!(
+            IoManager::new(mock.clone()).set_pin(0, true).await,
+            Err(ObnizError::PinInUse { pin: 0, .. })
+        ));
+        assert!(matches!(
+            animation
+                .start("broken", &[(0, HashMap::new())], Some(2))
+                .await,
+            Err(ObnizError::Generic(_))
+        ));
+
+        animation.stop("blink").await.unwrap();
+        IoManager::new(mock.clone()).set_pin(0, true).await.unwrap();
+        animation
+            .start("once", &IoAnimationManager::blink(2, 100), Some(3))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            mock.server().get_sent_messages(),
+            vec![
+                json!([{"io": {"animation": {"name": "blink", "status": "loop", "states": [
+                    {"duration": 500, "state": [{"io0": true}, {"io1": false}]},
+                    {"duration": 250, "state": [{"io0": false}]}
+                ]}}}]),
+                json!([{"io": {"animation": {"name": "blink", "status": "pause"}}}]),
+                json!([{"io": {"animation": {"name": "blink", "status": "resume"}}}]),
+                json!([{"io": {"animation": {"name": "blink", "status": "pause"}}}]),
+                json!([{"io0": true}]),
+                json!([{"io": {"animation": {"name": "once", "status": "loop", "repeat": 3, "states": [
+                    {"duration": 100, "state": [{"io2": true}]},
+                    {"duration": 100, "state": [{"io2": false}]}
+                ]}}}]),
+            ]
+        );
+    }
+}
@@ -1,22 +1,57 @@
 pub mod ad;
+pub mod callbacks;
+pub mod device;
 pub mod display;
+pub mod edge;
 pub mod error;
+pub mod events;
+pub mod gesture;
+#[cfg(feature = "embedded-hal")]
+pub mod hal;
+pub mod hardware;
 pub mod io;
+pub mod io_animation;
 pub mod obniz;
+pub mod options;
+pub mod pin;
 pub mod pwm;
+pub mod reconnect;
+pub mod resources;
+pub mod response;
+pub mod sequence;
+pub mod stream;
 pub mod switch;
 pub mod system;
+pub mod transport;
 pub mod uart;
 
 pub mod mock;
 
 pub use ad::*;
+pub use callbacks::*;
+pub use device::*;
 pub use display::*;
+pub use edge::*;
 pub use error::*;
+pub use events::*;
+pub use gesture::*;
+pub use hardware::*;
 pub use io::*;
+pub use io_animation::*;
 pub use mock::*;
 pub use obniz::*;
+pub use options::*;
+pub use pin::*;
 pub use pwm::*;
+pub use reconnect::*;
+pub use resources::*;
+pub use response::*;
+pub use sequence::*;
+pub use stream::*;
 pub use switch::*;
 pub use system::*;
+pub use transport::*;
 pub use uart::*;
+
+#[cfg(test)]
+mod testing;
@@ -4,8 +4,13 @@ use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
 use tokio_tungstenite::tungstenite::protocol::Message;
 
+use crate::callbacks::{next_subscription_id, CallbackRegistry, Subscription};
 use crate::error::{ObnizError, ObnizResult};
+use crate::hardware::HardwareProfile;
 use crate::obniz::{CallbackType, ObnizCommand};
+use crate::resources::ResourceRegistry;
+use crate::response::ObnizResponse;
+use crate::transport::ObnizTransport;
 
 /// Mock WebSocket message for testing
 #[derive(Debug, Clone)]
@@ -22,6 +27,8 @@ pub struct MockConfig {
     pub should_fail_connection: bool,
     pub should_timeout: bool,
     pub default_delay_ms: u64,
+    /// Board the mock reports to the managers
+    pub hardware: HardwareProfile,
 }
 
 impl Default for MockConfig {
@@ -31,6 +38,7 @@ impl Default for MockConfig {
             should_fail_connection: false,
             should_timeout: false,
             default_delay_ms: 10,
+            hardware: HardwareProfile::default(),
         }
     }
 }
@@ -41,7 +49,7 @@ pub struct MockWebSocketServer {
     config: MockConfig,
     message_handlers: Arc<Mutex<HashMap<String, MockMessage>>>,
     sent_messages: Arc<Mutex<Vec<Value>>>,
-    callbacks: Arc<Mutex<HashMap<String, CallbackType>>>,
+    callbacks: Arc<Mutex<CallbackRegistry>>,
 }
 
 impl MockWebSocketServer {
@@ -50,7 +58,7 @@ impl MockWebSocketServer {
             config,
             message_handlers: Arc::new(Mutex::new(HashMap::new())),
             sent_messages: Arc::new(Mutex::new(Vec::new())),
-            callbacks: Arc::new(Mutex::new(HashMap::new())),
+            callbacks: Arc::new(Mutex::new(CallbackRegistry::new())),
         }
     }
 
@@ -80,6 +88,11 @@ impl MockWebSocketServer {
             .insert(request_key.to_string(), mock_msg);
     }
 
+    /// Number of persistent callbacks listening under `key`
+    pub fn subscribers(&self, key: &str) -> usize {
+        self.callbacks.lock().unwrap().subscribers(key)
+    }
+
     /// Get all sent messages for verification
     pub fn get_sent_messages(&self) -> Vec<Value> {
         self.sent_messages.lock().unwrap().clone()
@@ -96,14 +109,7 @@ impl MockWebSocketServer {
             tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
         }
 
-        let text = message
-            .to_text()
-            .map_err(|_| ObnizError::Generic("Invalid message".to_string()))?;
-        let request: Value =
-            serde_json::from_str(text).map_err(|e| ObnizError::JsonParse(e.to_string()))?;
-
-        // Store sent message
-        self.sent_messages.lock().unwrap().push(request.clone());
+        let request = self.record_message(&message)?;
 
         // Find matching response
         let response = self.find_mock_response(&request);
@@ -119,6 +125,44 @@ impl MockWebSocketServer {
         }
     }
 
+    /// Like `process_message`, answering each command of a multi-command frame
+    pub async fn process_frame(&self, message: Message) -> ObnizResult<Vec<Value>> {
+        if self.config.should_timeout {
+            tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+        }
+
+        let request = self.record_message(&message)?;
+        let items = request.as_array().cloned().unwrap_or_default();
+
+        let mut responses = Vec::with_capacity(items.len());
+        for item in items {
+            let single = json!([item]);
+            match self.find_mock_response(&single) {
+                Some(mock_msg) => {
+                    if let Some(delay) = mock_msg.delay_ms {
+                        tokio::time::sleep(tokio::time::Duration::from_millis(delay)).await;
+                    }
+                    responses.push(mock_msg.response);
+                }
+                None => responses.push(self.generate_default_response(&single)),
+            }
+        }
+        Ok(responses)
+    }
+
+    /// Parse and store a sent message without producing a response
+    pub fn record_message(&self, message: &Message) -> ObnizResult<Value> {
+        let text = message
+            .to_text()
+            .map_err(|_| ObnizError::Generic("Invalid message".to_string()))?;
+        let request: Value =
+            serde_json::from_str(text).map_err(|e| ObnizError::JsonParse(e.to_string()))?;
+
+        // Store sent message
+        self.sent_messages.lock().unwrap().push(request.clone());
+        Ok(request)
+    }
+
     fn find_mock_response(&self, request: &Value) -> Option<MockMessage> {
         let handlers = self.message_handlers.lock().unwrap();
 
@@ -242,28 +286,34 @@ impl MockWebSocketServer {
 
     /// Simulate callback events
     pub async fn trigger_callback(&self, key: &str, data: Value) {
-        if let Some(callback) = self.callbacks.lock().unwrap().get(key) {
-            match callback {
-                CallbackType::Persistent(callback_fn) => {
-                    callback_fn(data);
-                }
-                CallbackType::OneShot(_) => {
-                    // OneShot callbacks are harder to trigger in tests
-                    // They would be consumed on first use
-                }
-            }
+        let response = ObnizResponse::from_frame(&data, key);
+        self.callbacks.lock().unwrap().dispatch(key, response);
+    }
+
+    /// Trigger switch `events` once someone listens on the switch
+    pub async fn press_switch(&self, events: &[(&str, &str)]) {
+        while self.subscribers("switch") == 0 {
+            tokio::task::yield_now().await;
+        }
+        for (state, action) in events {
+            self.trigger_callback(
+                "switch",
+                json!({"switch": {"state": state, "action": action}}),
+            )
+            .await;
         }
     }
 }
 
 /// Mock Obniz device for testing
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MockObniz {
     device_id: String,
     server: Arc<MockWebSocketServer>,
     command_sender: mpsc::UnboundedSender<ObnizCommand>,
     #[allow(dead_code)] // Reserved for future mock command processing
     command_receiver: Arc<Mutex<Option<mpsc::UnboundedReceiver<ObnizCommand>>>>,
+    resources: ResourceRegistry,
 }
 
 impl MockObniz {
@@ -276,6 +326,7 @@ impl MockObniz {
             server,
             command_sender: tx,
             command_receiver: Arc::new(Mutex::new(Some(rx))),
+            resources: ResourceRegistry::new(),
         }
     }
 
@@ -284,6 +335,7 @@ impl MockObniz {
     }
 
     pub async fn send_message(&self, message: Message) -> ObnizResult<()> {
+        self.server.record_message(&message)?;
         self.command_sender
             .send(ObnizCommand::Send {
                 message,
@@ -306,20 +358,56 @@ impl MockObniz {
         }
     }
 
+    pub async fn request_all(
+        &self,
+        message: Message,
+        response_keys: Vec<String>,
+    ) -> ObnizResult<Vec<ObnizResponse>> {
+        let frames = self.server.process_frame(message).await?;
+        response_keys
+            .iter()
+            .map(|key| {
+                frames
+                    .iter()
+                    .find_map(|frame| ObnizResponse::from_frame(frame, key).ok())
+                    .ok_or(ObnizError::Timeout)
+            })
+            .collect()
+    }
+
+    pub fn subscribe<F>(&self, key: String, callback: F) -> ObnizResult<Subscription>
+    where
+        F: Fn(ObnizResponse) + Send + Sync + 'static,
+    {
+        let id = next_subscription_id();
+        self.server.callbacks.lock().unwrap().register(
+            key.clone(),
+            CallbackType::Persistent {
+                id,
+                callback: Box::new(callback),
+            },
+        );
+
+        let server = self.server.clone();
+        let unsubscribe_key = key.clone();
+        Ok(Subscription::new(key, id, move || {
+            server
+                .callbacks
+                .lock()
+                .unwrap()
+                .unsubscribe(&unsubscribe_key, id);
+        }))
+    }
+
     pub fn register_callback<F>(&self, key: String, callback: F) -> ObnizResult<()>
     where
-        F: Fn(Value) + Send + Sync + 'static,
+        F: Fn(ObnizResponse) + Send + Sync + 'static,
     {
-        self.server
-            .callbacks
-            .lock()
-            .unwrap()
-            .insert(key, CallbackType::Persistent(Box::new(callback)));
-        Ok(())
+        self.subscribe(key, callback).map(Subscription::detach)
     }
 
     pub fn unregister_callback(&self, key: String) -> ObnizResult<()> {
-        self.server.callbacks.lock().unwrap().remove(&key);
+        self.server.callbacks.lock().unwrap().unregister(&key);
         Ok(())
     }
 
@@ -328,6 +416,48 @@ impl MockObniz {
     }
 }
 
+impl ObnizTransport for MockObniz {
+    fn send_message(&self, message: Message) -> ObnizResult<()> {
+        self.server.record_message(&message)?;
+        Ok(())
+    }
+
+    async fn send_await_response(
+        &self,
+        message: Message,
+        response_key: String,
+    ) -> ObnizResult<Value> {
+        MockObniz::send_await_response(self, message, response_key).await
+    }
+
+    async fn request_all(
+        &self,
+        message: Message,
+        response_keys: Vec<String>,
+    ) -> ObnizResult<Vec<ObnizResponse>> {
+        MockObniz::request_all(self, message, response_keys).await
+    }
+
+    fn subscribe<F>(&self, key: String, callback: F) -> ObnizResult<Subscription>
+    where
+        F: Fn(ObnizResponse) + Send + Sync + 'static,
+    {
+        MockObniz::subscribe(self, key, callback)
+    }
+
+    fn unregister_callback(&self, key: String) -> ObnizResult<()> {
+        MockObniz::unregister_callback(self, key)
+    }
+
+    fn resources(&self) -> &ResourceRegistry {
+        &self.resources
+    }
+
+    fn hardware_profile(&self) -> HardwareProfile {
+        self.server.config.hardware.clone()
+    }
+}
+
 /// Helper functions for creating mock responses
 pub mod responses {
     use super::*;
@@ -376,6 +506,8 @@ pub mod responses {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ad::AdManager;
+    use crate::io::IoManager;
 
     #[tokio::test]
     async fn test_mock_server_creation() {
@@ -422,6 +554,50 @@ mod tests {
         assert_eq!(response_value, json!([{"io0": false}]));
     }
 
+    #[tokio::test]
+    async fn test_managers_run_against_mock() {
+        let mock = MockObniz::new(MockConfig::default());
+        mock.server()
+            .add_response("io3", responses::io_pin_state(3, true));
+
+        let io = IoManager::new(mock.clone());
+        assert!(io.get_pin(3).await.unwrap());
+        io.set_pin(4, false).await.unwrap();
+
+        let ad = AdManager::new(mock.clone());
+        assert_eq!(ad.get_voltage(0).await.unwrap(), 3.3);
+
+        assert_eq!(
+            mock.server().get_sent_messages(),
+            vec![
+                json!([{"io3": "get"}]),
+                json!([{"io4": false}]),
+                json!([{"ad0": "get"}])
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mock_callbacks_through_transport() {
+        let mock = MockObniz::new(MockConfig::default());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+
+        let _subscription = IoManager::new(mock.clone())
+            .set_pin_callback(0, move |state| sink.lock().unwrap().push(state))
+            .await
+            .unwrap();
+
+        let server = mock.server();
+        server.trigger_callback("io0", json!({"io0": true})).await;
+        server.trigger_callback("io0", json!({"io0": false})).await;
+        assert_eq!(*received.lock().unwrap(), vec![true, false]);
+
+        ObnizTransport::unregister_callback(&mock, "io0".to_string()).unwrap();
+        server.trigger_callback("io0", json!({"io0": true})).await;
+        assert_eq!(received.lock().unwrap().len(), 2);
+    }
+
     #[tokio::test]
     async fn test_sent_message_tracking() {
         let config = MockConfig::default();
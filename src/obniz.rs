@@ -1,45 +1,61 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use anyhow::*;
-use futures::{
-    stream::{SplitSink, SplitStream},
-    SinkExt,
-};
+use anyhow::Context;
+use futures::stream::SplitSink;
+use futures::{SinkExt, Stream};
 use futures_util::StreamExt;
 use tokio::net::TcpStream;
-use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
+use tokio::time::{timeout, Instant};
 use tokio_tungstenite::{
-    connect_async as ws_connect_async, tungstenite::protocol::Message, MaybeTlsStream,
-    WebSocketStream,
+    connect_async_tls_with_config as ws_connect_async_tls_with_config,
+    tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
 };
 
 use serde_json::Value;
 
 use crate::ad::AdManager;
+use crate::callbacks::{next_subscription_id, CallbackRegistry, SocketRole, Subscription};
+use crate::device::ObnizDeviceInfo;
 use crate::display::DisplayManager;
+use crate::error::{ObnizError, ObnizResult};
+use crate::events::{
+    broadcast_stream, ConnectionEvent, ConnectionState, DeviceError, DeviceErrorLevel,
+    LifecycleEvents, EVENT_CHANNEL_CAPACITY,
+};
+use crate::hardware::HardwareProfile;
 use crate::io::IoManager;
+use crate::io_animation::IoAnimationManager;
+use crate::options::{ConnectOptions, ConnectionMode, ObnizBuilder};
 use crate::pwm::PwmManager;
+use crate::reconnect::{DeviceStateCache, ReconnectPolicy};
+use crate::resources::ResourceRegistry;
+use crate::response::ObnizResponse;
+use crate::sequence::Sequence;
 use crate::switch::SwitchManager;
 use crate::system::SystemManager;
 use crate::uart::UartManager;
 
-const OBNIZE_WEBSOKET_HOST: &str = "wss://obniz.io";
+const REDIRECT_TIMEOUT: Duration = Duration::from_secs(10);
 pub type ObnizWSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
-pub type CallbackFn = Box<dyn Fn(Value) + Send + Sync>;
-pub type ResponseSender = oneshot::Sender<Value>;
+pub type CallbackFn = Box<dyn Fn(ObnizResponse) + Send + Sync>;
+pub type ResponseSender = oneshot::Sender<ObnizResult<ObnizResponse>>;
 
 pub enum CallbackType {
     OneShot(ResponseSender),
-    Persistent(CallbackFn),
+    Persistent { id: u64, callback: CallbackFn },
 }
 
 impl std::fmt::Debug for CallbackType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             CallbackType::OneShot(_) => write!(f, "CallbackType::OneShot(_)"),
-            CallbackType::Persistent(_) => write!(f, "CallbackType::Persistent(_)"),
+            CallbackType::Persistent { id, .. } => {
+                write!(f, "CallbackType::Persistent {{ id: {id} }}")
+            }
         }
     }
 }
@@ -48,8 +64,64 @@ impl std::fmt::Debug for CallbackType {
 pub struct Obniz {
     id: String,
     sender: mpsc::UnboundedSender<ObnizCommand>,
+    /// LAN socket used for IO traffic in hybrid mode
+    local_sender: Option<mpsc::UnboundedSender<ObnizCommand>>,
     #[allow(dead_code)] // Used in WebSocket handler for callback routing
-    callbacks: Arc<RwLock<HashMap<String, CallbackType>>>,
+    callbacks: Arc<RwLock<CallbackRegistry>>,
+    events: LifecycleEvents,
+    device_errors: broadcast::Sender<DeviceError>,
+    /// Latest `ws.ready` report, updated on reconnect
+    device_info: Arc<Mutex<Option<ObnizDeviceInfo>>>,
+    /// Board set explicitly with `ObnizBuilder::hardware`
+    hardware: Option<HardwareProfile>,
+    resources: ResourceRegistry,
+    /// Default timeout for `send_await_response` (None = wait forever)
+    request_timeout: Option<Duration>,
+    /// Commands collected inside `batch()`, sent as one frame when it ends
+    batch: Option<Arc<Mutex<BatchQueue>>>,
+}
+
+/// Commands queued by the handles of one `batch()` call
+#[derive(Debug, Default)]
+struct BatchQueue {
+    items: Vec<Value>,
+    /// Set once `batch()` has flushed; handles kept past it send directly
+    closed: bool,
+}
+
+impl BatchQueue {
+    /// Take the queued commands, or None once the batch has ended
+    fn take(&mut self) -> Option<Vec<Value>> {
+        (!self.closed).then(|| std::mem::take(&mut self.items))
+    }
+}
+
+/// State shared by the WebSocket handler across reconnects
+struct HandlerContext {
+    obniz_id: String,
+    /// Which socket this handler drives; its waiters end with its sessions
+    socket: SocketRole,
+    callbacks: Arc<RwLock<CallbackRegistry>>,
+    events: LifecycleEvents,
+    device_errors: broadcast::Sender<DeviceError>,
+    device_info: Arc<Mutex<Option<ObnizDeviceInfo>>>,
+    options: ConnectOptions,
+}
+
+impl HandlerContext {
+    fn emit(&self, event: ConnectionEvent) {
+        self.events.emit(event);
+    }
+}
+
+/// Why a WebSocket session ended
+enum SessionEnd {
+    /// All `Obniz` handles were dropped
+    Closed,
+    /// The socket dropped or errored
+    Disconnected(String),
+    /// The server asked us to move to another host (`ws.redirect`)
+    Redirected(String),
 }
 
 #[derive(Debug)]
@@ -65,251 +137,653 @@ pub enum ObnizCommand {
     UnregisterCallback {
         key: String,
     },
+    /// Remove one persistent callback registered under `key`
+    Unsubscribe {
+        key: String,
+        id: u64,
+    },
+    /// Drop waiters under `key` whose response future is gone
+    PruneWaiters {
+        key: String,
+    },
+}
+
+/// Removes cancelled or timed out waiters from the callback registry
+struct WaiterGuard {
+    /// Weak, so a pending request never keeps the session open on its own
+    sender: mpsc::WeakUnboundedSender<ObnizCommand>,
+    keys: Vec<String>,
+}
+
+impl WaiterGuard {
+    fn disarm(&mut self) {
+        self.keys.clear();
+    }
+}
+
+impl Drop for WaiterGuard {
+    fn drop(&mut self) {
+        let Some(sender) = self.sender.upgrade() else {
+            return;
+        };
+        for key in self.keys.drain(..) {
+            let _ = sender.send(ObnizCommand::PruneWaiters { key });
+        }
+    }
 }
 
 impl Obniz {
-    async fn new(id: &str, api_url: url::Url) -> anyhow::Result<Obniz> {
-        let (socket, _response) = ws_connect_async(api_url.as_str())
-            .await
-            .context(format!("Failed to connect to {api_url}"))?;
+    fn new(
+        id: &str,
+        socket: ObnizWSocket,
+        local_socket: Option<ObnizWSocket>,
+        events: LifecycleEvents,
+        device_info: Option<ObnizDeviceInfo>,
+        options: ConnectOptions,
+    ) -> Obniz {
+        let callbacks = Arc::new(RwLock::new(CallbackRegistry::new()));
+        let device_info = Arc::new(Mutex::new(device_info));
+        let request_timeout = options.request_timeout;
+        let hardware = options.hardware.clone();
+        let (device_errors, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
 
-        let (write, read) = socket.split();
-        let (cmd_sender, cmd_receiver) = mpsc::unbounded_channel();
-        let callbacks = Arc::new(RwLock::new(HashMap::new()));
+        // The LAN socket reconnects to the device directly and does not report
+        // lifecycle events; those describe the cloud socket
+        let local_sender = match (local_socket, &options.mode) {
+            (Some(local_socket), ConnectionMode::Hybrid { ip }) => {
+                let context = HandlerContext {
+                    obniz_id: id.to_string(),
+                    socket: SocketRole::Io,
+                    callbacks: callbacks.clone(),
+                    events: LifecycleEvents::new(),
+                    device_errors: device_errors.clone(),
+                    device_info: device_info.clone(),
+                    options: ConnectOptions {
+                        mode: ConnectionMode::Local { ip: ip.clone() },
+                        ..options.clone()
+                    },
+                };
+                Some(Self::spawn_handler(local_socket, context))
+            }
+            _ => None,
+        };
+
+        let context = HandlerContext {
+            obniz_id: id.to_string(),
+            socket: SocketRole::Primary,
+            callbacks: callbacks.clone(),
+            events: events.clone(),
+            device_errors: device_errors.clone(),
+            device_info: device_info.clone(),
+            options,
+        };
+
+        Obniz {
+            id: id.to_string(),
+            sender: Self::spawn_handler(socket, context),
+            local_sender,
+            callbacks,
+            events,
+            device_errors,
+            device_info,
+            hardware,
+            resources: ResourceRegistry::new(),
+            request_timeout,
+            batch: None,
+        }
+    }
 
-        let callbacks_clone = callbacks.clone();
+    fn spawn_handler(
+        socket: ObnizWSocket,
+        context: HandlerContext,
+    ) -> mpsc::UnboundedSender<ObnizCommand> {
+        let (cmd_sender, cmd_receiver) = mpsc::unbounded_channel();
 
         // Spawn WebSocket handler task
         tokio::spawn(async move {
-            Self::websocket_handler(write, read, cmd_receiver, callbacks_clone).await;
+            Self::websocket_handler(socket, cmd_receiver, context).await;
         });
 
-        Ok(Obniz {
-            id: id.to_string(),
-            sender: cmd_sender,
-            callbacks,
-        })
+        cmd_sender
     }
 
     async fn websocket_handler(
-        mut write: SplitSink<ObnizWSocket, Message>,
-        mut read: SplitStream<ObnizWSocket>,
+        mut socket: ObnizWSocket,
         mut cmd_receiver: mpsc::UnboundedReceiver<ObnizCommand>,
-        callbacks: Arc<RwLock<HashMap<String, CallbackType>>>,
+        context: HandlerContext,
     ) {
+        let mut state = DeviceStateCache::new();
+
+        loop {
+            let session_end =
+                Self::run_session(socket, &mut cmd_receiver, &context, &mut state).await;
+
+            // Responses to requests sent on the old socket will never arrive;
+            // requests in flight on the other socket of hybrid mode are unaffected
+            context
+                .callbacks
+                .write()
+                .await
+                .clear_waiters_on(context.socket);
+
+            let reason = match session_end {
+                SessionEnd::Closed => break,
+                SessionEnd::Disconnected(reason) => reason,
+                SessionEnd::Redirected(host) => {
+                    context.emit(ConnectionEvent::Redirected { host: host.clone() });
+                    match connect_endpoint(&host, &context.obniz_id, &context.options).await {
+                        Ok(new_socket) => {
+                            socket = new_socket;
+                            context.emit(ConnectionEvent::Reconnected);
+                            continue;
+                        }
+                        Err(e) => {
+                            format!("Failed to follow redirect to {host}: {e}")
+                        }
+                    }
+                }
+            };
+            context.emit(ConnectionEvent::Disconnected { reason });
+
+            let Some(policy) = &context.options.reconnect else {
+                break;
+            };
+
+            socket = match Self::reconnect(&context, policy).await {
+                Some(socket) => socket,
+                None => break,
+            };
+            context.emit(ConnectionEvent::Reconnected);
+        }
+
+        context.emit(ConnectionEvent::Closed);
+    }
+
+    /// Pump commands and incoming messages until the socket or command channel closes
+    async fn run_session(
+        socket: ObnizWSocket,
+        cmd_receiver: &mut mpsc::UnboundedReceiver<ObnizCommand>,
+        context: &HandlerContext,
+        state: &mut DeviceStateCache,
+    ) -> SessionEnd {
+        let (mut write, mut read) = socket.split();
+        // Commands waiting for the batch window to close
+        let mut pending = Vec::new();
+        let mut flush_at: Option<Instant> = None;
+
+        // Restore the device configuration after a reconnect
+        if let Some(frame) = state.replay_frame() {
+            if let Err(e) = write.send(Message::from(frame.to_string())).await {
+                return SessionEnd::Disconnected(format!("Failed to replay device state: {e}"));
+            }
+        }
+
         loop {
             tokio::select! {
                 cmd = cmd_receiver.recv() => {
                     match cmd {
                         Some(ObnizCommand::Send { message, response_key: _ }) => {
-                            if let Err(e) = write.send(message).await {
-                                eprintln!("Failed to send message: {e}");
+                            let frame = frame_value(&message);
+                            // Recorded even if the send fails so a reconnect still applies it
+                            if let Some(frame) = &frame {
+                                state.record(frame);
+                            }
+                            let result = match (context.options.batch_window, frame) {
+                                (Some(window), Some(Value::Array(items))) => {
+                                    flush_at.get_or_insert_with(|| Instant::now() + window);
+                                    pending.extend(items);
+                                    Ok(())
+                                }
+                                // Anything else goes out right away, after what is already queued
+                                _ => {
+                                    flush_at = None;
+                                    match Self::flush_pending(&mut write, &mut pending).await {
+                                        Ok(()) => write.send(message).await,
+                                        Err(e) => Err(e),
+                                    }
+                                }
+                            };
+                            if let Err(e) = result {
+                                return SessionEnd::Disconnected(format!("Failed to send message: {e}"));
                             }
                         }
                         Some(ObnizCommand::RegisterCallback { key, callback }) => {
-                            callbacks.write().await.insert(key, callback);
+                            context
+                                .callbacks
+                                .write()
+                                .await
+                                .register_on(context.socket, key, callback);
                         }
                         Some(ObnizCommand::UnregisterCallback { key }) => {
-                            callbacks.write().await.remove(&key);
+                            context.callbacks.write().await.unregister(&key);
+                        }
+                        Some(ObnizCommand::Unsubscribe { key, id }) => {
+                            context.callbacks.write().await.unsubscribe(&key, id);
+                        }
+                        Some(ObnizCommand::PruneWaiters { key }) => {
+                            context.callbacks.write().await.prune_waiters(&key);
                         }
-                        None => break,
+                        None => {
+                            let _ = Self::flush_pending(&mut write, &mut pending).await;
+                            let _ = write.close().await;
+                            return SessionEnd::Closed;
+                        }
+                    }
+                }
+                _ = tokio::time::sleep_until(flush_at.unwrap_or_else(Instant::now)), if flush_at.is_some() => {
+                    flush_at = None;
+                    if let Err(e) = Self::flush_pending(&mut write, &mut pending).await {
+                        return SessionEnd::Disconnected(format!("Failed to send message: {e}"));
                     }
                 }
                 message = read.next() => {
                     match message {
-                        Some(result) => {
-                            match result {
-                                std::result::Result::Ok(msg) => {
-                                    if let Err(e) = Self::handle_incoming_message(msg, &callbacks).await {
-                                        eprintln!("Failed to handle message: {e}");
-                                    }
-                                }
-                                std::result::Result::Err(e) => {
-                                    eprintln!("WebSocket error: {e}");
+                        Some(Ok(Message::Close(frame))) => {
+                            let reason = frame
+                                .map(|frame| frame.reason.to_string())
+                                .unwrap_or_else(|| "closed by server".to_string());
+                            return SessionEnd::Disconnected(reason);
+                        }
+                        Some(Ok(msg)) if msg.is_text() => {
+                            match Self::handle_incoming_message(msg, context).await {
+                                Ok(Some(host)) => {
+                                    let _ = write.close().await;
+                                    return SessionEnd::Redirected(host);
                                 }
+                                Ok(None) => {}
+                                Err(e) => context.emit(ConnectionEvent::InvalidMessage {
+                                    reason: format!("{e:#}"),
+                                }),
                             }
                         }
-                        None => break,
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => {
+                            return SessionEnd::Disconnected(format!("WebSocket error: {e}"));
+                        }
+                        None => return SessionEnd::Disconnected("connection lost".to_string()),
                     }
                 }
             }
         }
     }
 
+    /// Send the commands collected during the batch window as one frame
+    async fn flush_pending(
+        write: &mut SplitSink<ObnizWSocket, Message>,
+        pending: &mut Vec<Value>,
+    ) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let frame = Value::Array(std::mem::take(pending));
+        write.send(Message::from(frame.to_string())).await
+    }
+
+    /// Re-resolve the redirect host and open a new socket, backing off between attempts
+    async fn reconnect(context: &HandlerContext, policy: &ReconnectPolicy) -> Option<ObnizWSocket> {
+        let mut attempt = 0;
+        while policy.should_retry(attempt) {
+            tokio::time::sleep(policy.delay(attempt)).await;
+            attempt += 1;
+
+            match open_socket(&context.obniz_id, &context.options, &context.events).await {
+                Ok(socket) => return Some(socket),
+                Err(e) => context.emit(ConnectionEvent::ReconnectFailed {
+                    attempt,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        None
+    }
+
+    /// Route an incoming message to callbacks.
+    /// Returns the new host when the message is a `ws.redirect`.
     async fn handle_incoming_message(
         message: Message,
-        callbacks: &Arc<RwLock<HashMap<String, CallbackType>>>,
-    ) -> anyhow::Result<()> {
+        context: &HandlerContext,
+    ) -> anyhow::Result<Option<String>> {
         let text = message
             .to_text()
             .context("Failed to parse message as text")?;
         let value: Value = serde_json::from_str(text).context("Failed to parse JSON")?;
 
-        let mut keys_to_remove = Vec::new();
-
-        // Route message to appropriate callback
-        {
-            let callbacks_guard = callbacks.read().await;
+        if let Some(host) = redirect_host_from_message(&value) {
+            context.options.validate_host(&host)?;
+            return Ok(Some(host));
+        }
 
-            // Check if it's an array response (typical obniz format)
-            if let Some(array) = value.as_array() {
-                for item in array {
-                    let mut remove_keys =
-                        Self::route_message_to_callback(item, &callbacks_guard).await?;
-                    keys_to_remove.append(&mut remove_keys);
+        if let Some(items) = value.as_array() {
+            for item in items {
+                if let Some(info) = ObnizDeviceInfo::from_ws_message(item) {
+                    *context.device_info.lock().unwrap() = Some(info);
+                }
+                if let Some(event) = ConnectionEvent::from_ws_message(item) {
+                    context.emit(event);
                 }
-            } else {
-                let mut remove_keys =
-                    Self::route_message_to_callback(&value, &callbacks_guard).await?;
-                keys_to_remove.append(&mut remove_keys);
             }
         }
 
-        // Handle OneShot callbacks - send response and remove from map
-        if !keys_to_remove.is_empty() {
-            let mut callbacks_guard = callbacks.write().await;
-            for key in keys_to_remove {
-                if let Some(CallbackType::OneShot(sender)) = callbacks_guard.remove(&key) {
-                    // Send the response through the channel
-                    if sender.send(value.clone()).is_err() {
-                        eprintln!("Failed to send response through oneshot channel for key: {key}");
-                    }
+        // Parse each message once; subscribers and waiters share the typed response
+        let items = match value.as_array() {
+            Some(items) => items.iter().collect::<Vec<_>>(),
+            None => vec![&value],
+        };
+        let mut callbacks = context.callbacks.write().await;
+        for item in items {
+            let Some(obj) = item.as_object() else {
+                continue;
+            };
+            for report in DeviceError::from_debug_message(item) {
+                // A request the device rejected would otherwise wait for its timeout
+                if let (DeviceErrorLevel::Error, Some(module)) = (report.level, &report.module) {
+                    callbacks.fail_waiters(module, || ObnizError::Device {
+                        module: module.clone(),
+                        message: report.message.clone(),
+                    });
                 }
+                let _ = context.device_errors.send(report);
+            }
+            for (key, field) in obj {
+                callbacks.dispatch(key, ObnizResponse::parse(key, field));
             }
         }
 
-        Ok(())
+        Ok(None)
     }
 
-    async fn route_message_to_callback(
-        message: &Value,
-        callbacks: &HashMap<String, CallbackType>,
-    ) -> anyhow::Result<Vec<String>> {
-        let mut keys_to_remove = Vec::new();
-
-        // Extract callback key from message structure
-        let callback_key = Self::extract_callback_key(message);
-
-        if let Some(key) = callback_key {
-            if let Some(callback) = callbacks.get(&key) {
-                match callback {
-                    CallbackType::OneShot(_sender) => {
-                        // For OneShot callbacks, we need to signal that this key should be removed
-                        // The actual sending will be handled in the websocket_handler
-                        keys_to_remove.push(key.clone());
-                    }
-                    CallbackType::Persistent(callback_fn) => {
-                        callback_fn(message.clone());
-                    }
-                }
-            }
+    /// The LAN socket for IO traffic in hybrid mode, otherwise (or when the
+    /// LAN socket is gone) the cloud socket. Commands belonging together, such
+    /// as a request and its waiters, must take the same route.
+    fn route(&self, prefer_local: bool) -> &mpsc::UnboundedSender<ObnizCommand> {
+        match &self.local_sender {
+            Some(local_sender) if prefer_local && !local_sender.is_closed() => local_sender,
+            _ => &self.sender,
         }
+    }
 
-        Ok(keys_to_remove)
-    }
-
-    fn extract_callback_key(message: &Value) -> Option<String> {
-        // Handle array responses first (most common format from obniz)
-        if let Some(array) = message.as_array() {
-            if let Some(first_item) = array.first() {
-                if let Some(obj) = first_item.as_object() {
-                    for (key, _) in obj {
-                        // Check for various obniz response patterns
-                        if key.starts_with("io")
-                            || key.starts_with("ad")
-                            || key.starts_with("pwm")
-                            || key.starts_with("uart")
-                            || key == "display"
-                            || key == "switch"
-                            || key == "system"
-                        {
-                            return Some(key.clone());
-                        }
-                    }
-                }
-            }
-        }
+    fn dispatch(&self, command: ObnizCommand, prefer_local: bool) -> anyhow::Result<()> {
+        self.route(prefer_local)
+            .send(command)
+            .map_err(|e| anyhow::anyhow!("Failed to send command: {e}"))
+    }
 
-        // Check for direct object responses (fallback)
-        if let Some(obj) = message.as_object() {
-            for (key, _) in obj {
-                if key.starts_with("io")
-                    || key.starts_with("ad")
-                    || key.starts_with("pwm")
-                    || key.starts_with("uart")
-                    || key == "display"
-                    || key == "switch"
-                    || key == "system"
-                {
-                    return Some(key.clone());
+    pub fn send_message(&self, msg: Message) -> anyhow::Result<()> {
+        if let Some(batch) = &self.batch {
+            if let Some(Value::Array(items)) = frame_value(&msg) {
+                let mut queue = batch.lock().unwrap();
+                if !queue.closed {
+                    queue.items.extend(items);
+                    return Ok(());
                 }
             }
         }
 
-        None
-    }
-
-    pub fn send_message(&self, msg: Message) -> anyhow::Result<()> {
-        self.sender
-            .send(ObnizCommand::Send {
+        let prefer_local = is_io_frame(&msg);
+        self.dispatch(
+            ObnizCommand::Send {
                 message: msg,
                 response_key: None,
-            })
-            .context("Failed to send command")
+            },
+            prefer_local,
+        )
     }
 
+    /// Send a message and wait for the response under `response_key`,
+    /// giving up after the default request timeout. The frame is rebuilt
+    /// from the parsed response as `[{key: value}]`, without the other keys
+    /// of the message it arrived in
     pub async fn send_await_response(
         &self,
         msg: Message,
         response_key: String,
     ) -> anyhow::Result<Value> {
-        let (tx, rx) = oneshot::channel::<Value>();
+        self.send_await_response_with_timeout(msg, response_key, self.request_timeout)
+            .await
+    }
 
-        // Register callback for response
-        self.sender
-            .send(ObnizCommand::RegisterCallback {
-                key: response_key.clone(),
-                callback: CallbackType::OneShot(tx),
-            })
-            .context("Failed to register callback")?;
+    /// Like `send_await_response`, with a timeout for this call only (None = wait forever).
+    /// Fails with `ObnizError::Timeout` when no response arrives in time.
+    pub async fn send_await_response_with_timeout(
+        &self,
+        msg: Message,
+        response_key: String,
+        request_timeout: Option<Duration>,
+    ) -> anyhow::Result<Value> {
+        let response = self
+            .request_with_timeout(msg, response_key, request_timeout)
+            .await?;
+        Ok(Value::Array(vec![response.to_item()]))
+    }
+
+    /// Send a message and wait for the typed response under `response_key`
+    pub async fn request(&self, msg: Message, response_key: String) -> ObnizResult<ObnizResponse> {
+        self.request_with_timeout(msg, response_key, self.request_timeout)
+            .await
+    }
+
+    /// Like `request`, with a timeout for this call only (None = wait forever)
+    pub async fn request_with_timeout(
+        &self,
+        msg: Message,
+        response_key: String,
+        request_timeout: Option<Duration>,
+    ) -> ObnizResult<ObnizResponse> {
+        let mut responses = self
+            .request_all_with_timeout(msg, vec![response_key], request_timeout)
+            .await?;
+        Ok(responses.remove(0))
+    }
+
+    /// Send one message and wait for the typed response under each of `response_keys`,
+    /// returned in the same order (e.g. several `get`s in one frame)
+    pub async fn request_all(
+        &self,
+        msg: Message,
+        response_keys: Vec<String>,
+    ) -> ObnizResult<Vec<ObnizResponse>> {
+        self.request_all_with_timeout(msg, response_keys, self.request_timeout)
+            .await
+    }
+
+    /// Like `request_all`, with one timeout covering every response
+    pub async fn request_all_with_timeout(
+        &self,
+        msg: Message,
+        response_keys: Vec<String>,
+        request_timeout: Option<Duration>,
+    ) -> ObnizResult<Vec<ObnizResponse>> {
+        // Inside a batch the request carries the commands queued before it
+        let queued = self
+            .batch
+            .as_ref()
+            .and_then(|batch| batch.lock().unwrap().take());
+        let msg = match queued {
+            Some(mut items) => match frame_value(&msg) {
+                Some(Value::Array(request)) => {
+                    items.extend(request);
+                    Message::from(Value::Array(items).to_string())
+                }
+                _ => {
+                    self.flush_batch(items)
+                        .map_err(|e| ObnizError::Connection(e.to_string()))?;
+                    msg
+                }
+            },
+            None => msg,
+        };
+
+        let sender = self.route(is_io_frame(&msg)).clone();
+
+        // From here on, dropping this future or timing out removes the waiters again
+        let mut guard = WaiterGuard {
+            sender: sender.downgrade(),
+            keys: Vec::with_capacity(response_keys.len()),
+        };
+
+        // Register callbacks for the responses on the same socket so they are in place first
+        let mut receivers = Vec::with_capacity(response_keys.len());
+        for key in &response_keys {
+            let (tx, rx) = oneshot::channel();
+            sender
+                .send(ObnizCommand::RegisterCallback {
+                    key: key.clone(),
+                    callback: CallbackType::OneShot(tx),
+                })
+                .map_err(|e| ObnizError::Connection(format!("Failed to register callback: {e}")))?;
+            guard.keys.push(key.clone());
+            receivers.push(rx);
+        }
 
         // Send message
-        self.sender
+        sender
             .send(ObnizCommand::Send {
                 message: msg,
-                response_key: Some(response_key.clone()),
+                response_key: response_keys.first().cloned(),
             })
-            .context("Failed to send message")?;
+            .map_err(|e| ObnizError::Connection(format!("Failed to send message: {e}")))?;
 
-        // Wait for response (the callback will be automatically removed after receiving)
-        let result = rx.await.context("Failed to receive response")?;
+        // Wait for the responses (each waiter is removed from its queue once answered)
+        let responses = futures::future::join_all(receivers);
+        let results = match request_timeout {
+            Some(duration) => timeout(duration, responses)
+                .await
+                .map_err(|_| ObnizError::Timeout)?,
+            None => responses.await,
+        };
+        guard.disarm();
 
-        Ok(result)
+        results
+            .into_iter()
+            .map(|result| -> ObnizResult<ObnizResponse> { result? })
+            .collect()
     }
 
-    pub fn register_callback<F>(&self, key: String, callback: F) -> anyhow::Result<()>
+    /// Run `f` with a handle whose commands are queued and sent as a single frame
+    /// when `f` returns. Requests that wait for a response inside the batch take
+    /// the queued commands with them. Queued commands are sent even if `f` fails.
+    /// Clones of the handle that outlive `batch()` send their commands directly.
+    pub async fn batch<F, Fut, R>(&self, f: F) -> ObnizResult<R>
+    where
+        F: FnOnce(Obniz) -> Fut,
+        Fut: Future<Output = ObnizResult<R>>,
+    {
+        // Nested batches join the outer one
+        let in_batch = self
+            .batch
+            .as_ref()
+            .is_some_and(|batch| !batch.lock().unwrap().closed);
+        if in_batch {
+            return f(self.clone()).await;
+        }
+
+        let batch = Arc::new(Mutex::new(BatchQueue::default()));
+        let handle = Obniz {
+            batch: Some(batch.clone()),
+            ..self.clone()
+        };
+        let result = f(handle).await;
+
+        let items = {
+            let mut queue = batch.lock().unwrap();
+            queue.closed = true;
+            std::mem::take(&mut queue.items)
+        };
+        self.flush_batch(items)
+            .map_err(|e| ObnizError::Connection(e.to_string()))?;
+        result
+    }
+
+    fn flush_batch(&self, items: Vec<Value>) -> anyhow::Result<()> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        let msg = Message::from(Value::Array(items).to_string());
+        let prefer_local = is_io_frame(&msg);
+        self.dispatch(
+            ObnizCommand::Send {
+                message: msg,
+                response_key: None,
+            },
+            prefer_local,
+        )
+    }
+
+    /// Call `callback` with the parsed response for every message under `key`
+    /// until the returned subscription is dropped. Other subscribers on the key
+    /// are unaffected; messages that fail to parse are not delivered.
+    pub fn subscribe<F>(&self, key: String, callback: F) -> anyhow::Result<Subscription>
     where
-        F: Fn(Value) + Send + Sync + 'static,
+        F: Fn(ObnizResponse) + Send + Sync + 'static,
     {
-        self.sender
+        let id = next_subscription_id();
+        // Registered on the socket that carries requests for `key`, so it is in
+        // place before their responses arrive
+        let sender = self.route(is_io_key(&key)).clone();
+        sender
             .send(ObnizCommand::RegisterCallback {
-                key,
-                callback: CallbackType::Persistent(Box::new(callback)),
+                key: key.clone(),
+                callback: CallbackType::Persistent {
+                    id,
+                    callback: Box::new(callback),
+                },
             })
-            .context("Failed to register callback")
+            .context("Failed to register callback")?;
+
+        // Weak handles, so a live subscription does not keep the session open
+        // after the last `Obniz` is dropped
+        let sender = sender.downgrade();
+        let fallback = self.sender.downgrade();
+        let unsubscribe_key = key.clone();
+        Ok(Subscription::new(key, id, move || {
+            let command = ObnizCommand::Unsubscribe {
+                key: unsubscribe_key,
+                id,
+            };
+            // The LAN socket may be gone by now; the registry is shared, so the cloud one will do
+            let command = match sender.upgrade() {
+                Some(sender) => match sender.send(command) {
+                    Ok(()) => return,
+                    Err(mpsc::error::SendError(command)) => command,
+                },
+                None => command,
+            };
+            if let Some(fallback) = fallback.upgrade() {
+                let _ = fallback.send(command);
+            }
+        }))
+    }
+
+    /// Register a callback that stays until `unregister_callback`
+    pub fn register_callback<F>(&self, key: String, callback: F) -> anyhow::Result<()>
+    where
+        F: Fn(ObnizResponse) + Send + Sync + 'static,
+    {
+        self.subscribe(key, callback).map(Subscription::detach)
     }
 
+    /// Remove every callback registered under `key`
     pub fn unregister_callback(&self, key: String) -> anyhow::Result<()> {
-        self.sender
+        self.route(is_io_key(&key))
             .send(ObnizCommand::UnregisterCallback { key })
             .context("Failed to unregister callback")
     }
 
+    /// Start a sequence of commands that is sent as one frame
+    pub fn sequence(&self) -> Sequence {
+        Sequence::new(self.clone())
+    }
+
     /// Get the IO manager for this Obniz device
     pub fn io(&self) -> IoManager {
         IoManager::new(self.clone())
     }
 
+    /// Get the IO animation manager for this Obniz device
+    pub fn io_animation(&self) -> IoAnimationManager {
+        IoAnimationManager::new(self.clone())
+    }
+
     /// Get the display manager for this Obniz device
     pub fn display(&self) -> DisplayManager {
         DisplayManager::new(self.clone())
@@ -340,57 +814,316 @@ impl Obniz {
         SwitchManager::new(self.clone())
     }
 
+    /// Start building a connection with custom options
+    pub fn builder(obniz_id: &str) -> ObnizBuilder {
+        ObnizBuilder::new(obniz_id)
+    }
+
     /// Get the device ID
     pub fn id(&self) -> &str {
         &self.id
     }
+
+    /// Hardware, firmware and network the device reported in `ws.ready`,
+    /// or None if it has not reported ready yet
+    pub fn device_info(&self) -> Option<ObnizDeviceInfo> {
+        self.device_info.lock().unwrap().clone()
+    }
+
+    /// Board used to validate pins and peripherals: the one set on the builder,
+    /// else the one matching the reported `hw`, else an obniz Board
+    pub fn hardware_profile(&self) -> HardwareProfile {
+        if let Some(profile) = &self.hardware {
+            return profile.clone();
+        }
+        self.device_info()
+            .filter(|info| !info.hw.is_empty())
+            .map(|info| HardwareProfile::from_hw(&info.hw))
+            .unwrap_or_default()
+    }
+
+    /// Which module owns each IO pin
+    pub fn resources(&self) -> &ResourceRegistry {
+        &self.resources
+    }
+
+    /// Default timeout for requests that wait for a response
+    pub fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+    }
+
+    /// Handle to the same device with a different default request timeout,
+    /// e.g. `obniz.with_request_timeout(Some(Duration::from_secs(1))).io().get_pin(0)`
+    pub fn with_request_timeout(&self, request_timeout: Option<Duration>) -> Obniz {
+        Obniz {
+            request_timeout,
+            ..self.clone()
+        }
+    }
+
+    /// Stream of connection lifecycle events (connect, ready, disconnect, reconnect, close).
+    /// Only events emitted after the call are delivered; subscribe on the builder
+    /// with `ObnizBuilder::connection_events` to also see those emitted while connecting.
+    pub fn connection_events(&self) -> impl Stream<Item = ConnectionEvent> + Send + 'static {
+        self.events.subscribe()
+    }
+
+    /// State of the cloud (or, in local mode, LAN) socket as of the last lifecycle event
+    pub fn connection_state(&self) -> ConnectionState {
+        self.events.state()
+    }
+
+    /// Stream of errors and warnings reported by the device firmware in `debug` messages.
+    /// Only reports received after the call are delivered.
+    pub fn device_errors(&self) -> impl Stream<Item = DeviceError> + Send + 'static {
+        broadcast_stream(self.device_errors.subscribe())
+    }
+}
+
+pub async fn connect_async(obniz_id: &str) -> ObnizResult<Obniz> {
+    connect_with_options(obniz_id, ConnectOptions::default()).await
 }
 
-pub async fn connect_async(obniz_id: &str) -> anyhow::Result<Obniz> {
-    let redirect_host = get_redirect_host(obniz_id).context("failed to get redirect host name")?;
-    let api_url = endpoint_url(&redirect_host, obniz_id)?;
-    Obniz::new(obniz_id, api_url)
+/// Connect and transparently reconnect with backoff when the WebSocket drops.
+/// The last configuration of every IO pin, AD, PWM and UART channel is replayed
+/// to the device once the new socket is open.
+pub async fn connect_async_with_reconnect(
+    obniz_id: &str,
+    policy: ReconnectPolicy,
+) -> ObnizResult<Obniz> {
+    ObnizBuilder::new(obniz_id)
+        .reconnect(policy)
+        .connect()
         .await
-        .context("failed to create Obniz object")
 }
 
-// Synchronous connect function is deprecated - use connect_async instead
+/// Connect straight to the device on the LAN, skipping the obniz cloud
+pub async fn connect_local(ip: &str, obniz_id: &str) -> ObnizResult<Obniz> {
+    ObnizBuilder::new(obniz_id).local(ip).connect().await
+}
 
-fn endpoint_url(host: &str, obniz_id: &str) -> anyhow::Result<url::Url> {
-    if !host.starts_with("wss://") {
-        return Err(anyhow!("Illegal url, host needs to start with 'wss://'"));
-    }
+/// Connect through the cloud and additionally to the device on the LAN.
+/// IO commands go over the LAN socket; everything else uses the cloud socket.
+pub async fn connect_hybrid(obniz_id: &str, ip: &str) -> ObnizResult<Obniz> {
+    ObnizBuilder::new(obniz_id).hybrid(ip).connect().await
+}
 
-    let endpoint = format!("{host}/obniz/{obniz_id}/ws/1");
-    url::Url::parse(&endpoint).context("Failed to parse endpoint url")
+/// Connect with custom endpoint, token, TLS and reconnect settings
+pub async fn connect_with_options(obniz_id: &str, options: ConnectOptions) -> ObnizResult<Obniz> {
+    connect_with_events(obniz_id, options, LifecycleEvents::new()).await
 }
 
-fn get_redirect_host(obniz_id: &str) -> anyhow::Result<String> {
-    let url = endpoint_url(OBNIZE_WEBSOKET_HOST, obniz_id)?;
-    //Websokcet接続
-    let (mut ws_stream, _response) =
-        tungstenite::connect(url.as_str()).context("Failed to connect")?;
-
-    let message = ws_stream.read().context("Fail to read message")?;
-    //　接続するとリダイレクトアドレスが入ったjsonが返るのでパースする
-    let message = message.to_text().context("fail to parse text")?;
-
-    let res: Value = serde_json::from_str(message).context("Failed to parse json")?;
-    let json_redirect_host = &res[0]["ws"]["redirect"];
-    let redirect_host = match json_redirect_host.as_str() {
-        // ダブルクォートが入るので除去するためにstrに一旦する
-        Some(host) => host.to_string(),
-        None => return Err(anyhow!("Failed to get redirect host name")),
+/// `connect_with_options`, reporting progress through `events`
+pub(crate) async fn connect_with_events(
+    obniz_id: &str,
+    options: ConnectOptions,
+    events: LifecycleEvents,
+) -> ObnizResult<Obniz> {
+    let socket = open_socket(obniz_id, &options, &events).await?;
+    let (socket, device_info) = match options.ready_timeout {
+        Some(limit) => {
+            let (socket, info) = wait_ready(socket, obniz_id, &options, &events, limit).await?;
+            (socket, Some(info))
+        }
+        None => (socket, None),
     };
-    println!("redirect_host : {redirect_host}");
-    if redirect_host.is_empty() {
-        return Err(anyhow!("Redirect host name is empty"));
+
+    // Hybrid mode still works over the cloud if the LAN socket cannot be opened
+    let local_socket = match &options.mode {
+        ConnectionMode::Hybrid { ip } => {
+            match connect_url(options.local_url(ip)?, &options).await {
+                Ok(local_socket) => Some(local_socket),
+                Err(e) => {
+                    events.emit(ConnectionEvent::LocalUnavailable {
+                        ip: ip.clone(),
+                        reason: e.to_string(),
+                    });
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    events.set_state(ConnectionState::Connected);
+
+    Ok(Obniz::new(
+        obniz_id,
+        socket,
+        local_socket,
+        events,
+        device_info,
+        options,
+    ))
+}
+
+/// Read messages until the device reports `ws.ready`, following a `ws.redirect` on the way
+async fn wait_ready(
+    mut socket: ObnizWSocket,
+    obniz_id: &str,
+    options: &ConnectOptions,
+    events: &LifecycleEvents,
+    limit: Duration,
+) -> ObnizResult<(ObnizWSocket, ObnizDeviceInfo)> {
+    let read_ready = async {
+        loop {
+            let message = match socket.next().await {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => {
+                    return Err(ObnizError::Connection(format!(
+                        "Failed to read ready message: {e}"
+                    )))
+                }
+                None => {
+                    return Err(ObnizError::Connection(
+                        "Connection closed before the device was ready".to_string(),
+                    ))
+                }
+            };
+            let Some(value) = frame_value(&message) else {
+                continue;
+            };
+
+            if let Some(host) = redirect_host_from_message(&value) {
+                options.validate_host(&host)?;
+                events.emit(ConnectionEvent::Redirected { host: host.clone() });
+                let _ = socket.close(None).await;
+                socket = connect_endpoint(&host, obniz_id, options).await?;
+                continue;
+            }
+
+            let items = match value.as_array() {
+                Some(items) => items.iter().collect::<Vec<_>>(),
+                None => vec![&value],
+            };
+            for item in items {
+                if let Some(info) = ObnizDeviceInfo::from_ws_message(item) {
+                    if let Some(event) = ConnectionEvent::from_ws_message(item) {
+                        events.emit(event);
+                    }
+                    return Ok(info);
+                }
+            }
+        }
+    };
+
+    let info = timeout(limit, read_ready).await.map_err(|_| {
+        ObnizError::Connection("Timed out waiting for the device to be ready".to_string())
+    })??;
+    Ok((socket, info))
+}
+
+/// Resolve the redirect host and open the device socket
+async fn open_socket(
+    obniz_id: &str,
+    options: &ConnectOptions,
+    events: &LifecycleEvents,
+) -> ObnizResult<ObnizWSocket> {
+    events.emit(ConnectionEvent::Connecting);
+    if let ConnectionMode::Local { ip } = &options.mode {
+        return connect_url(options.local_url(ip)?, options).await;
     }
-    if !redirect_host.starts_with("wss://") {
-        return Err(anyhow!("Redirect host name is bad format"));
+    if options.skip_redirect {
+        return connect_endpoint(&options.host, obniz_id, options).await;
     }
 
-    Ok(redirect_host)
+    let redirect_host = get_redirect_host(obniz_id, options).await?;
+    events.emit(ConnectionEvent::Redirected {
+        host: redirect_host.clone(),
+    });
+    connect_endpoint(&redirect_host, obniz_id, options).await
+}
+
+async fn connect_endpoint(
+    host: &str,
+    obniz_id: &str,
+    options: &ConnectOptions,
+) -> ObnizResult<ObnizWSocket> {
+    connect_url(options.endpoint_url(host, obniz_id)?, options).await
+}
+
+async fn connect_url(url: url::Url, options: &ConnectOptions) -> ObnizResult<ObnizWSocket> {
+    let connector = options.connector()?;
+    let handshake = ws_connect_async_tls_with_config(url.as_str(), None, false, connector);
+    let host = url.host_str().unwrap_or_default();
+    let (socket, _response) = timeout(options.connect_timeout, handshake)
+        .await
+        .map_err(|_| ObnizError::Connection(format!("Timed out connecting to {host}")))?
+        .map_err(|e| ObnizError::Connection(format!("Failed to connect to {host}: {e}")))?;
+    Ok(socket)
+}
+
+async fn get_redirect_host(obniz_id: &str, options: &ConnectOptions) -> ObnizResult<String> {
+    let mut socket = connect_endpoint(&options.host, obniz_id, options).await?;
+
+    // 接続するとリダイレクトアドレスが入ったjsonが返るのでパースする
+    let read_redirect = async {
+        while let Some(message) = socket.next().await {
+            let message = message.map_err(|e| {
+                ObnizError::Connection(format!("Failed to read redirect message: {e}"))
+            })?;
+            let Ok(text) = message.to_text() else {
+                continue;
+            };
+            let Ok(value) = serde_json::from_str::<Value>(text) else {
+                continue;
+            };
+            if let Some(host) = redirect_host_from_message(&value) {
+                options.validate_host(&host)?;
+                return Ok(host);
+            }
+        }
+        Err(ObnizError::Connection(
+            "Connection closed before redirect host was received".to_string(),
+        ))
+    };
+
+    let redirect_host = timeout(REDIRECT_TIMEOUT, read_redirect)
+        .await
+        .map_err(|_| ObnizError::Connection("Timed out waiting for redirect host".to_string()))?;
+    let _ = socket.close(None).await;
+    redirect_host
+}
+
+/// Parse an outgoing text frame
+fn frame_value(message: &Message) -> Option<Value> {
+    message
+        .to_text()
+        .ok()
+        .and_then(|text| serde_json::from_str::<Value>(text).ok())
+}
+
+/// Check whether a frame only carries IO pin commands (`ioX` keys)
+fn is_io_frame(message: &Message) -> bool {
+    let Some(value) = frame_value(message) else {
+        return false;
+    };
+    let Some(items) = value.as_array() else {
+        return false;
+    };
+
+    !items.is_empty()
+        && items.iter().all(|item| {
+            item.as_object()
+                .is_some_and(|obj| !obj.is_empty() && obj.keys().all(|key| is_io_key(key)))
+        })
+}
+
+/// Check whether `key` names an IO pin (`io3`)
+fn is_io_key(key: &str) -> bool {
+    key.strip_prefix("io")
+        .is_some_and(|pin| !pin.is_empty() && pin.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Find `ws.redirect` in a message such as `[{"ws": {"redirect": "wss://..."}}]`
+fn redirect_host_from_message(message: &Value) -> Option<String> {
+    let items = match message.as_array() {
+        Some(array) => array.iter().collect::<Vec<_>>(),
+        None => vec![message],
+    };
+    items
+        .into_iter()
+        .find_map(|item| item.get("ws")?.get("redirect")?.as_str())
+        .map(|host| host.to_string())
 }
 
 // Legacy enums moved to display module - kept here for backward compatibility
@@ -398,14 +1131,525 @@ pub use crate::display::{DisplayRawColorDepth, ObnizDisplay, QrCorrectionType};
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::testing::{fake_device, fake_device_with_ready};
+    use serde_json::json;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn test_redirect_host_extraction() {
+        let message = json!([{"ws": {"redirect": "wss://jp1.obniz.io"}}]);
+        assert_eq!(
+            redirect_host_from_message(&message),
+            Some("wss://jp1.obniz.io".to_string())
+        );
+
+        let message = json!({"ws": {"redirect": "wss://us1.obniz.io"}});
+        assert_eq!(
+            redirect_host_from_message(&message),
+            Some("wss://us1.obniz.io".to_string())
+        );
+
+        assert_eq!(redirect_host_from_message(&json!([{"io0": true}])), None);
+    }
+
+    #[test]
+    fn test_io_frame_detection() {
+        let io = |value: Value| Message::from(value.to_string());
+
+        assert!(is_io_frame(&io(json!([{"io0": true}]))));
+        assert!(is_io_frame(&io(
+            json!([{"io0": true, "io1": false}, {"io11": "get"}])
+        )));
+        assert!(!is_io_frame(&io(
+            json!([{"io0": true}, {"display": {"clear": true}}])
+        )));
+        assert!(!is_io_frame(&io(json!([{"io": {"animation": {}}}]))));
+        assert!(!is_io_frame(&io(json!([]))));
+        assert!(!is_io_frame(&Message::from("not json")));
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_removes_waiter() {
+        let (builder, mut device) = fake_device(|_| None).await;
+        let obniz = builder
+            .request_timeout(Some(Duration::from_millis(50)))
+            .connect()
+            .await
+            .unwrap();
+
+        let result = obniz.io().get_pin(0).await;
+        assert!(matches!(result, Err(ObnizError::Timeout)));
+        assert_eq!(device.next_frame().await, json!([{"io0": "get"}]));
+
+        // The guard's prune command is handled right after the timeout
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(obniz.callbacks.read().await.pending("io0"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_bus_read_sends_one_frame_and_combines_replies() {
+        let (builder, mut device) = fake_device(|frame| {
+            (frame == &json!([{"io0": "get"}, {"io1": "get"}, {"io2": "get"}]))
+                .then(|| json!([{"io2": true}, {"io0": true}, {"io1": false}]))
+        })
+        .await;
+        let obniz = builder.connect().await.unwrap();
+
+        assert_eq!(
+            obniz.io().bus(&[0, 1, 2]).unwrap().read().await.unwrap(),
+            0b101
+        );
+        assert_eq!(
+            device.next_frame().await,
+            json!([{"io0": "get"}, {"io1": "get"}, {"io2": "get"}])
+        );
+        for key in ["io0", "io1", "io2"] {
+            assert_eq!(obniz.callbacks.read().await.pending(key), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_per_call_timeout_override() {
+        let (builder, _device) = fake_device(|frame| {
+            (frame == &json!([{"io1": "get"}])).then(|| json!([{"io1": true}]))
+        })
+        .await;
+        let obniz = builder.request_timeout(None).connect().await.unwrap();
+        assert_eq!(obniz.request_timeout(), None);
+
+        let quick = obniz.with_request_timeout(Some(Duration::from_millis(50)));
+        assert!(matches!(
+            quick.io().get_pin(2).await,
+            Err(ObnizError::Timeout)
+        ));
+        assert!(quick.io().get_pin(1).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_share_key_with_subscriber() {
+        let (builder, device) = fake_device(|_| None).await;
+        let obniz = builder.connect().await.unwrap();
+
+        let (seen_tx, mut seen_rx) = mpsc::unbounded_channel();
+        obniz
+            .register_callback("io3".to_string(), move |value| {
+                let _ = seen_tx.send(value);
+            })
+            .unwrap();
+
+        let first = tokio::spawn({
+            let obniz = obniz.clone();
+            async move { obniz.io().get_pin(3).await }
+        });
+        let second = tokio::spawn({
+            let obniz = obniz.clone();
+            async move { obniz.io().get_pin(3).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        device.push.send(json!([{"io3": true}])).unwrap();
+        device.push.send(json!([{"io3": false}])).unwrap();
+
+        let mut results = vec![
+            first.await.unwrap().unwrap(),
+            second.await.unwrap().unwrap(),
+        ];
+        results.sort();
+        assert_eq!(results, vec![false, true]);
+        assert_eq!(
+            seen_rx.recv().await,
+            Some(ObnizResponse::Io {
+                pin: 3,
+                value: true
+            })
+        );
+        assert_eq!(
+            seen_rx.recv().await,
+            Some(ObnizResponse::Io {
+                pin: 3,
+                value: false
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_window_coalesces_commands() {
+        let (builder, mut device) = fake_device(|_| None).await;
+        let obniz = builder
+            .batch_window(Some(Duration::from_millis(20)))
+            .connect()
+            .await
+            .unwrap();
+
+        obniz.io().set_pin(0, true).await.unwrap();
+        obniz.io().set_pin(1, false).await.unwrap();
+        obniz.display().clear().await.unwrap();
+
+        assert_eq!(
+            device.next_frame().await,
+            json!([{"io0": true}, {"io1": false}, {"display": {"clear": true}}])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_handle_kept_past_batch_sends_directly() {
+        let (builder, mut device) = fake_device(|_| None).await;
+        let obniz = builder.connect().await.unwrap();
+
+        let kept = obniz
+            .batch(|b| async move {
+                b.io().set_pin(0, true).await?;
+                Ok(b)
+            })
+            .await
+            .unwrap();
+        assert_eq!(device.next_frame().await, json!([{"io0": true}]));
+
+        kept.io().set_pin(1, false).await.unwrap();
+        assert_eq!(device.next_frame().await, json!([{"io1": false}]));
+
+        // A batch started from the stale handle is a fresh one, not a nested one
+        kept.batch(|b| async move {
+            b.io().set_pin(2, true).await?;
+            b.io().set_pin(3, true).await
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            device.next_frame().await,
+            json!([{"io2": true}, {"io3": true}])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_explicit_batch_sends_one_frame() {
+        let (builder, mut device) = fake_device(|frame| {
+            let items = frame.as_array()?;
+            items
+                .iter()
+                .any(|item| item.get("io5") == Some(&json!("get")))
+                .then(|| json!([{"io5": true}]))
+        })
+        .await;
+        let obniz = builder.connect().await.unwrap();
+
+        obniz
+            .batch(|b| async move {
+                b.io().set_pin(0, true).await?;
+                b.io().set_pin(1, true).await
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            device.next_frame().await,
+            json!([{"io0": true}, {"io1": true}])
+        );
+
+        let state = obniz
+            .batch(|b| async move {
+                b.io().set_pin(2, false).await?;
+                b.io().get_pin(5).await
+            })
+            .await
+            .unwrap();
+        assert!(state);
+        assert_eq!(
+            device.next_frame().await,
+            json!([{"io2": false}, {"io5": "get"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dropped_request_removes_waiter() {
+        let (builder, mut device) = fake_device(|_| None).await;
+        let obniz = builder.connect().await.unwrap();
+
+        let request = tokio::spawn({
+            let obniz = obniz.clone();
+            async move { obniz.system().status().await }
+        });
+        device.next_frame().await;
+        assert_eq!(obniz.callbacks.read().await.pending("system"), 1);
+
+        request.abort();
+        let _ = request.await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(obniz.callbacks.read().await.pending("system"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_live_subscription_does_not_keep_session_open() {
+        let (builder, _device) = fake_device(|_| None).await;
+        let mut events = Box::pin(builder.connection_events());
+        let obniz = builder.connect().await.unwrap();
+        let subscription = obniz.subscribe("io0".to_string(), |_| {}).unwrap();
+
+        drop(obniz);
+        let closed = async {
+            while let Some(event) = events.next().await {
+                if event == ConnectionEvent::Closed {
+                    return;
+                }
+            }
+            panic!("event stream ended before Closed");
+        };
+        tokio::time::timeout(Duration::from_secs(5), closed)
+            .await
+            .expect("session stayed open");
+        drop(subscription);
+    }
+
+    /// `ip:port` of a fake device, for local and hybrid mode
+    fn device_address(builder: &ObnizBuilder) -> String {
+        builder
+            .options()
+            .host
+            .trim_start_matches("ws://")
+            .to_string()
+    }
+
+    fn answer_io0_get(level: bool) -> impl Fn(&Value) -> Option<Value> + Send + 'static {
+        move |frame| (frame[0]["io0"] == "get").then(|| json!([{"io0": level}]))
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_routes_io_requests_and_subscriptions_over_lan() {
+        let (builder, mut cloud) = fake_device(answer_io0_get(false)).await;
+        let (lan_builder, mut lan) = fake_device(answer_io0_get(true)).await;
+        let obniz = builder
+            .hybrid(&device_address(&lan_builder))
+            .connect()
+            .await
+            .unwrap();
+
+        // The subscription is in place before the LAN answer to the `get` arrives
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let _sub = obniz
+            .subscribe("io0".to_string(), move |value| {
+                sink.lock().unwrap().push(value)
+            })
+            .unwrap();
+        assert!(obniz.io().pin(0).unwrap().get().await.unwrap());
+        assert_eq!(lan.next_frame().await, json!([{"io0": "get"}]));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![ObnizResponse::Io {
+                pin: 0,
+                value: true
+            }]
+        );
+
+        // A cloud request survives the LAN socket going away
+        let request = tokio::spawn({
+            let obniz = obniz.clone();
+            async move {
+                obniz
+                    .request(
+                        Message::from(json!([{"ad0": "get"}]).to_string()),
+                        "ad0".to_string(),
+                    )
+                    .await
+            }
+        });
+        assert_eq!(cloud.next_frame().await, json!([{"ad0": "get"}]));
+        drop(lan);
+        let local_sender = obniz.local_sender.clone().unwrap();
+        while !local_sender.is_closed() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(obniz.callbacks.read().await.pending("ad0"), 1);
+        cloud.push.send(json!([{"ad0": 1.5}])).unwrap();
+        assert!(request.await.unwrap().is_ok());
+
+        // IO falls back to the cloud once the LAN socket is gone
+        assert!(!obniz.io().pin(0).unwrap().get().await.unwrap());
+        assert_eq!(cloud.next_frame().await, json!([{"io0": "get"}]));
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_timeout_prunes_waiter_on_lan_socket() {
+        let (builder, _cloud) = fake_device(|_| None).await;
+        let (lan_builder, mut lan) = fake_device(|_| None).await;
+        let obniz = builder
+            .hybrid(&device_address(&lan_builder))
+            .request_timeout(Some(Duration::from_millis(50)))
+            .connect()
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            obniz.io().pin(0).unwrap().get().await,
+            Err(ObnizError::Timeout)
+        ));
+        assert_eq!(lan.next_frame().await, json!([{"io0": "get"}]));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(obniz.callbacks.read().await.pending("io0"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_local_mode_talks_to_device_directly() {
+        let (device_builder, mut device) = fake_device(answer_io0_get(true)).await;
+        let obniz = ObnizBuilder::new(crate::testing::FAKE_DEVICE_ID)
+            .local(&device_address(&device_builder))
+            .connect()
+            .await
+            .unwrap();
+        assert!(obniz.local_sender.is_none());
+
+        assert!(obniz.io().pin(0).unwrap().get().await.unwrap());
+        assert_eq!(device.next_frame().await, json!([{"io0": "get"}]));
+        assert_eq!(obniz.callbacks.read().await.pending("io0"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_send_gives_claimed_pins_back() {
+        let (builder, device) = fake_device(|_| None).await;
+        let obniz = builder.connect().await.unwrap();
+        drop(device);
+        while !obniz.sender.is_closed() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(obniz.io().set_pin(3, true).await.is_err());
+        assert!(obniz
+            .sequence()
+            .io(4, true)
+            .pwm_init(0, 5)
+            .send()
+            .await
+            .is_err());
+        assert!(obniz
+            .io_animation()
+            .start("blink", &IoAnimationManager::blink(6, 100), None)
+            .await
+            .is_err());
+        for pin in 3..=6 {
+            assert_eq!(obniz.resources().owner(pin), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_waits_for_ready() {
+        let (builder, _device) = fake_device(|_| None).await;
+        let obniz = builder.connect().await.unwrap();
+
+        let info = obniz.device_info().unwrap();
+        assert_eq!(info.hw, "obnizb1");
+        assert!(info.firmware_at_least(3, 5, 0));
+        assert_eq!(obniz.hardware_profile(), HardwareProfile::obniz_board());
+    }
+
+    #[tokio::test]
+    async fn test_hardware_profile_follows_reported_board() {
+        let ready =
+            json!([{"ws": {"ready": true, "obniz": {"hw": "m5stickc", "firmware": "3.5.0"}}}]);
+        let (builder, _device) = fake_device_with_ready(Some(ready), |_| None).await;
+        let obniz = builder.connect().await.unwrap();
+        assert_eq!(obniz.hardware_profile(), HardwareProfile::m5stickc());
+        assert!(obniz.display().clear().await.is_ok());
+        assert!(matches!(
+            obniz.io().set_pin(3, true).await,
+            Err(ObnizError::UnsupportedPin { pin: 3, .. })
+        ));
+
+        let (builder, _device) = fake_device(|_| None).await;
+        let obniz = builder
+            .hardware(HardwareProfile::esp32("esp32w"))
+            .connect()
+            .await
+            .unwrap();
+        assert!(matches!(
+            obniz.display().clear().await,
+            Err(ObnizError::UnsupportedFeature { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_ready_timeout() {
+        let (builder, _device) = fake_device_with_ready(None, |_| None).await;
+        let result = builder
+            .ready_timeout(Some(Duration::from_millis(50)))
+            .connect()
+            .await;
+        assert!(matches!(result, Err(ObnizError::Connection(_))));
+
+        let (builder, _device) = fake_device_with_ready(None, |_| None).await;
+        let obniz = builder.ready_timeout(None).connect().await.unwrap();
+        assert_eq!(obniz.device_info(), None);
+    }
+
+    #[tokio::test]
+    async fn test_builder_subscribers_see_connect_events() {
+        let (builder, device) = fake_device(|_| None).await;
+        // Nothing listens on port 1, so the LAN socket fails and IO uses the cloud
+        let builder = builder.hybrid("127.0.0.1:1");
+        let mut events = Box::pin(builder.connection_events());
+        let obniz = builder.connect().await.unwrap();
+        assert_eq!(obniz.connection_state(), ConnectionState::Connected);
+
+        assert_eq!(events.next().await, Some(ConnectionEvent::Connecting));
+        assert!(matches!(
+            events.next().await,
+            Some(ConnectionEvent::Ready { hw, .. }) if hw == "obnizb1"
+        ));
+        assert!(matches!(
+            events.next().await,
+            Some(ConnectionEvent::LocalUnavailable { ip, .. }) if ip == "127.0.0.1:1"
+        ));
+
+        device
+            .push
+            .send(json!([{"ws": {"redirect": "http://insecure.example"}}]))
+            .unwrap();
+        assert!(matches!(
+            events.next().await,
+            Some(ConnectionEvent::InvalidMessage { .. })
+        ));
+
+        drop(device);
+        assert!(matches!(
+            events.next().await,
+            Some(ConnectionEvent::Disconnected { .. })
+        ));
+        assert_eq!(events.next().await, Some(ConnectionEvent::Closed));
+        assert_eq!(obniz.connection_state(), ConnectionState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_device_error_fails_pending_request() {
+        let (builder, _device) = fake_device(|frame| {
+            (frame == &json!([{"io3": "get"}]))
+                .then(|| json!([{"debug": {"error": {"module": "io3", "message": "overcurrent"}}}]))
+        })
+        .await;
+        let obniz = builder.request_timeout(None).connect().await.unwrap();
+        let mut errors = Box::pin(obniz.device_errors());
+
+        let result = obniz.io().get_pin(3).await;
+        assert!(matches!(
+            result,
+            Err(ObnizError::Device { module, message }) if module == "io3" && message == "overcurrent"
+        ));
+        assert_eq!(
+            errors.next().await,
+            Some(DeviceError {
+                level: DeviceErrorLevel::Error,
+                module: Some("io3".to_string()),
+                message: "overcurrent".to_string(),
+            })
+        );
+    }
 }
 
 // The following modules are now implemented in separate files:
 // - IO: src/io.rs
+// - IoAnimation: src/io_animation.rs
 // - AD: src/ad.rs
 // - PWM: src/pwm.rs
 // - UART: src/uart.rs
@@ -414,7 +1658,6 @@ mod tests {
 // - System: src/system.rs
 
 // Future features (not yet implemented):
-// - IoAnimation: Complex IO state animations
 // - TCP: Network communication
 // - SPI: Serial Peripheral Interface
 // - I2C: Inter-Integrated Circuit
@@ -0,0 +1,359 @@
+use std::time::Duration;
+
+use futures::Stream;
+use tokio_tungstenite::Connector;
+
+use crate::error::{ObnizError, ObnizResult};
+use crate::events::{ConnectionEvent, LifecycleEvents};
+use crate::hardware::HardwareProfile;
+use crate::obniz::{connect_with_events, Obniz};
+use crate::reconnect::ReconnectPolicy;
+
+pub const DEFAULT_OBNIZ_HOST: &str = "wss://obniz.io";
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Which sockets to open to the device
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ConnectionMode {
+    /// Through the obniz cloud
+    #[default]
+    Cloud,
+    /// Straight to the device's LAN IP, skipping the cloud
+    Local { ip: String },
+    /// Cloud socket plus a LAN socket that carries IO traffic
+    Hybrid { ip: String },
+}
+
+/// Connection settings for an obniz device
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    /// Base host, e.g. `wss://obniz.io` or `ws://localhost:3000`
+    pub host: String,
+    /// API access token sent as the `access_token` query parameter
+    pub access_token: Option<String>,
+    /// Allow plain `ws://` hosts (local stand-ins, CI)
+    pub allow_insecure: bool,
+    /// Connect to `host` directly instead of asking it for a redirect host
+    pub skip_redirect: bool,
+    /// Timeout for each WebSocket handshake
+    pub connect_timeout: Duration,
+    /// How long `connect` waits for the device's `ws.ready` (None = return once the socket opens)
+    pub ready_timeout: Option<Duration>,
+    /// Extra PEM-encoded root certificates trusted for TLS
+    pub tls_root_certificates: Vec<Vec<u8>>,
+    /// Reconnect automatically when the socket drops
+    pub reconnect: Option<ReconnectPolicy>,
+    /// Cloud, LAN or both
+    pub mode: ConnectionMode,
+    /// Default timeout for requests awaiting a response (None = wait forever)
+    pub request_timeout: Option<Duration>,
+    /// Merge commands queued within this window into one frame (None = send each at once)
+    pub batch_window: Option<Duration>,
+    /// Board to validate against (None = pick from the `hw` reported in `ws.ready`)
+    pub hardware: Option<HardwareProfile>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            host: DEFAULT_OBNIZ_HOST.to_string(),
+            access_token: None,
+            allow_insecure: false,
+            skip_redirect: false,
+            connect_timeout: Duration::from_secs(10),
+            ready_timeout: Some(DEFAULT_READY_TIMEOUT),
+            tls_root_certificates: Vec::new(),
+            reconnect: None,
+            mode: ConnectionMode::Cloud,
+            request_timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            batch_window: None,
+            hardware: None,
+        }
+    }
+}
+
+impl ConnectOptions {
+    /// Check that a host uses an allowed scheme
+    pub fn validate_host(&self, host: &str) -> ObnizResult<()> {
+        if host.is_empty() {
+            return Err(ObnizError::Connection("Host name is empty".to_string()));
+        }
+        if host.starts_with("wss://") || (self.allow_insecure && host.starts_with("ws://")) {
+            Ok(())
+        } else if self.allow_insecure {
+            Err(ObnizError::Connection(format!(
+                "Illegal url '{host}', host needs to start with 'wss://' or 'ws://'"
+            )))
+        } else {
+            Err(ObnizError::Connection(format!(
+                "Illegal url '{host}', host needs to start with 'wss://'"
+            )))
+        }
+    }
+
+    /// Build the device endpoint on a given host
+    pub fn endpoint_url(&self, host: &str, obniz_id: &str) -> ObnizResult<url::Url> {
+        self.validate_host(host)?;
+
+        let endpoint = format!("{}/obniz/{obniz_id}/ws/1", host.trim_end_matches('/'));
+        let mut url = url::Url::parse(&endpoint)
+            .map_err(|e| ObnizError::Connection(format!("Failed to parse endpoint url: {e}")))?;
+        if let Some(token) = &self.access_token {
+            url.query_pairs_mut().append_pair("access_token", token);
+        }
+        Ok(url)
+    }
+
+    /// Endpoint of the device's local WebSocket server
+    pub fn local_url(&self, ip: &str) -> ObnizResult<url::Url> {
+        if ip.is_empty() {
+            return Err(ObnizError::Connection(
+                "Local IP address is empty".to_string(),
+            ));
+        }
+        url::Url::parse(&format!("ws://{ip}/"))
+            .map_err(|e| ObnizError::Connection(format!("Invalid local address '{ip}': {e}")))
+    }
+
+    /// TLS connector with the custom root certificates, if any were given
+    pub(crate) fn connector(&self) -> ObnizResult<Option<Connector>> {
+        if self.tls_root_certificates.is_empty() {
+            return Ok(None);
+        }
+
+        let mut builder = native_tls::TlsConnector::builder();
+        for pem in &self.tls_root_certificates {
+            let certificate = native_tls::Certificate::from_pem(pem)
+                .map_err(|e| ObnizError::Connection(format!("Invalid root certificate: {e}")))?;
+            builder.add_root_certificate(certificate);
+        }
+        let connector = builder
+            .build()
+            .map_err(|e| ObnizError::Connection(format!("Failed to build TLS connector: {e}")))?;
+        Ok(Some(Connector::NativeTls(connector)))
+    }
+}
+
+/// Builder for connecting to an obniz device with custom options
+#[derive(Debug, Clone)]
+pub struct ObnizBuilder {
+    obniz_id: String,
+    options: ConnectOptions,
+    /// Handed to the connection, so subscribers see the events of `connect` itself
+    events: LifecycleEvents,
+}
+
+impl ObnizBuilder {
+    pub fn new(obniz_id: &str) -> Self {
+        Self {
+            obniz_id: obniz_id.to_string(),
+            options: ConnectOptions::default(),
+            events: LifecycleEvents::new(),
+        }
+    }
+
+    /// Base host (default `wss://obniz.io`)
+    pub fn host(mut self, host: &str) -> Self {
+        self.options.host = host.to_string();
+        self
+    }
+
+    /// API access token
+    pub fn access_token(mut self, token: &str) -> Self {
+        self.options.access_token = Some(token.to_string());
+        self
+    }
+
+    /// Allow plain `ws://` hosts
+    pub fn allow_insecure(mut self, allow: bool) -> Self {
+        self.options.allow_insecure = allow;
+        self
+    }
+
+    /// Connect to the host directly without the redirect step
+    pub fn skip_redirect(mut self, skip: bool) -> Self {
+        self.options.skip_redirect = skip;
+        self
+    }
+
+    /// Timeout for each WebSocket handshake
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.options.connect_timeout = timeout;
+        self
+    }
+
+    /// How long `connect` waits for the device to report ready (None = don't wait)
+    pub fn ready_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.options.ready_timeout = timeout;
+        self
+    }
+
+    /// Trust an additional PEM-encoded root certificate
+    pub fn add_root_certificate(mut self, pem: &[u8]) -> Self {
+        self.options.tls_root_certificates.push(pem.to_vec());
+        self
+    }
+
+    /// Reconnect automatically when the socket drops
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.options.reconnect = Some(policy);
+        self
+    }
+
+    /// Connect straight to the device on the LAN
+    pub fn local(mut self, ip: &str) -> Self {
+        self.options.mode = ConnectionMode::Local { ip: ip.to_string() };
+        self
+    }
+
+    /// Connect through the cloud and send IO traffic over the LAN
+    pub fn hybrid(mut self, ip: &str) -> Self {
+        self.options.mode = ConnectionMode::Hybrid { ip: ip.to_string() };
+        self
+    }
+
+    /// Default timeout for requests awaiting a response (None = wait forever)
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.options.request_timeout = timeout;
+        self
+    }
+
+    /// Merge commands queued within `window` into one frame
+    pub fn batch_window(mut self, window: Option<Duration>) -> Self {
+        self.options.batch_window = window;
+        self
+    }
+
+    /// Validate pins and peripherals against `profile` instead of the reported board
+    pub fn hardware(mut self, profile: HardwareProfile) -> Self {
+        self.options.hardware = Some(profile);
+        self
+    }
+
+    /// Get the options collected so far
+    pub fn options(&self) -> &ConnectOptions {
+        &self.options
+    }
+
+    /// Lifecycle events of the connection this builder opens, starting with
+    /// `Connecting`, `Redirected` and `Ready` from `connect` itself
+    pub fn connection_events(&self) -> impl Stream<Item = ConnectionEvent> + Send + 'static {
+        self.events.subscribe()
+    }
+
+    /// Connect to the device
+    pub async fn connect(self) -> ObnizResult<Obniz> {
+        connect_with_events(&self.obniz_id, self.options, self.events).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_options() {
+        let options = ConnectOptions::default();
+        assert_eq!(options.host, "wss://obniz.io");
+        assert!(options.access_token.is_none());
+        assert!(!options.allow_insecure);
+        assert!(!options.skip_redirect);
+        assert!(options.reconnect.is_none());
+        assert_eq!(options.request_timeout, Some(DEFAULT_REQUEST_TIMEOUT));
+        assert_eq!(options.ready_timeout, Some(DEFAULT_READY_TIMEOUT));
+    }
+
+    #[test]
+    fn test_endpoint_url() {
+        let options = ConnectOptions::default();
+        let url = options.endpoint_url("wss://obniz.io", "1234-5678").unwrap();
+        assert_eq!(url.as_str(), "wss://obniz.io/obniz/1234-5678/ws/1");
+        assert!(options.endpoint_url("ws://obniz.io", "1234-5678").is_err());
+    }
+
+    #[test]
+    fn test_endpoint_url_with_token_and_insecure_host() {
+        let options = ObnizBuilder::new("1234-5678")
+            .access_token("secret token")
+            .allow_insecure(true)
+            .options()
+            .clone();
+
+        let url = options
+            .endpoint_url("ws://localhost:3000/", "1234-5678")
+            .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "ws://localhost:3000/obniz/1234-5678/ws/1?access_token=secret+token"
+        );
+    }
+
+    #[test]
+    fn test_host_validation() {
+        let options = ConnectOptions::default();
+        assert!(options.validate_host("wss://jp1.obniz.io").is_ok());
+        assert!(matches!(
+            options.validate_host(""),
+            Err(ObnizError::Connection(_))
+        ));
+        assert!(matches!(
+            options.validate_host("http://jp1.obniz.io"),
+            Err(ObnizError::Connection(_))
+        ));
+    }
+
+    #[test]
+    fn test_builder_collects_options() {
+        let builder = ObnizBuilder::new("1234-5678")
+            .host("wss://staging.obniz.io")
+            .skip_redirect(true)
+            .connect_timeout(Duration::from_secs(3))
+            .reconnect(ReconnectPolicy::default());
+
+        let options = builder.options();
+        assert_eq!(options.host, "wss://staging.obniz.io");
+        assert!(options.skip_redirect);
+        assert_eq!(options.connect_timeout, Duration::from_secs(3));
+        assert_eq!(options.reconnect, Some(ReconnectPolicy::default()));
+    }
+
+    #[test]
+    fn test_local_modes() {
+        let builder = ObnizBuilder::new("1234-5678").local("192.168.0.10");
+        assert_eq!(
+            builder.options().mode,
+            ConnectionMode::Local {
+                ip: "192.168.0.10".to_string()
+            }
+        );
+
+        let builder = ObnizBuilder::new("1234-5678").hybrid("192.168.0.10");
+        assert_eq!(
+            builder.options().mode,
+            ConnectionMode::Hybrid {
+                ip: "192.168.0.10".to_string()
+            }
+        );
+
+        let options = ConnectOptions::default();
+        assert_eq!(
+            options.local_url("192.168.0.10").unwrap().as_str(),
+            "ws://192.168.0.10/"
+        );
+        assert!(options.local_url("").is_err());
+    }
+
+    #[test]
+    fn test_invalid_root_certificate() {
+        let options = ObnizBuilder::new("1234-5678")
+            .add_root_certificate(b"not a certificate")
+            .options()
+            .clone();
+        assert!(matches!(
+            options.connector(),
+            Err(ObnizError::Connection(_))
+        ));
+        assert!(ConnectOptions::default().connector().unwrap().is_none());
+    }
+}
@@ -0,0 +1,345 @@
+use std::time::Duration;
+
+use crate::callbacks::Subscription;
+use crate::edge::{CounterConfig, Edge, IoCounter};
+use crate::error::{ObnizError, ObnizResult};
+use crate::io::{Direction, IoConfig, IoPin, OutputType, PullType};
+use crate::obniz::Obniz;
+use crate::stream::InputStream;
+use crate::transport::ObnizTransport;
+
+/// Push-pull output pin, from `IoPin::into_output`
+#[derive(Debug)]
+pub struct OutputPin<T: ObnizTransport = Obniz> {
+    pin: IoPin<T>,
+    config: IoConfig,
+}
+
+/// Input pin, from `IoPin::into_input`
+#[derive(Debug)]
+pub struct InputPin<T: ObnizTransport = Obniz> {
+    pin: IoPin<T>,
+    config: IoConfig,
+}
+
+/// Open-drain pin that drives low or releases the line, from `IoPin::into_open_drain`
+#[derive(Debug)]
+pub struct OpenDrainPin<T: ObnizTransport = Obniz> {
+    pin: IoPin<T>,
+    config: IoConfig,
+}
+
+/// Accept only the push-pull output types; open-drain pins have their own handle
+fn push_pull(pin: u8, output_type: OutputType) -> ObnizResult<OutputType> {
+    match output_type {
+        OutputType::OpenDrain => Err(ObnizError::IoOperation(format!(
+            "io{pin}: OutputPin is push-pull only, use into_open_drain for open-drain output"
+        ))),
+        output_type => Ok(output_type),
+    }
+}
+
+impl<T: ObnizTransport> IoPin<T> {
+    /// Configure as a push-pull output, initially low. `OutputType::OpenDrain`
+    /// is rejected; use `into_open_drain` instead.
+    pub async fn into_output(self, output_type: OutputType) -> ObnizResult<OutputPin<T>> {
+        let output_type = push_pull(self.pin(), output_type)?;
+        let config = IoConfig {
+            direction: Direction::Output,
+            value: Some(false),
+            output_type: Some(output_type),
+            pull_type: None,
+            stream: None,
+        };
+        self.configure(config.clone()).await?;
+        Ok(OutputPin { pin: self, config })
+    }
+
+    /// Configure as an input with `pull_type`
+    pub async fn into_input(self, pull_type: PullType) -> ObnizResult<InputPin<T>> {
+        let config = IoConfig {
+            direction: Direction::Input,
+            value: None,
+            output_type: None,
+            pull_type: Some(pull_type),
+            stream: Some(false),
+        };
+        self.configure(config.clone()).await?;
+        Ok(InputPin { pin: self, config })
+    }
+
+    /// Configure as an open-drain output with `pull_type`, initially released
+    pub async fn into_open_drain(self, pull_type: PullType) -> ObnizResult<OpenDrainPin<T>> {
+        let config = IoConfig {
+            direction: Direction::Output,
+            value: Some(true),
+            output_type: Some(OutputType::OpenDrain),
+            pull_type: Some(pull_type),
+            stream: None,
+        };
+        self.configure(config.clone()).await?;
+        Ok(OpenDrainPin { pin: self, config })
+    }
+}
+
+impl<T: ObnizTransport> OutputPin<T> {
+    pub fn pin(&self) -> u8 {
+        self.pin.pin()
+    }
+
+    /// Configuration currently applied to the pin
+    pub fn config(&self) -> &IoConfig {
+        &self.config
+    }
+
+    pub async fn set(&mut self, value: bool) -> ObnizResult<()> {
+        self.pin.set(value).await?;
+        self.config.value = Some(value);
+        Ok(())
+    }
+
+    pub async fn set_high(&mut self) -> ObnizResult<()> {
+        self.set(true).await
+    }
+
+    pub async fn set_low(&mut self) -> ObnizResult<()> {
+        self.set(false).await
+    }
+
+    /// Invert the last value written
+    pub async fn toggle(&mut self) -> ObnizResult<()> {
+        let value = !self.config.value.unwrap_or(false);
+        self.set(value).await
+    }
+
+    /// Switch between the push-pull output types; `OutputType::OpenDrain` is rejected
+    pub async fn set_output_type(&mut self, output_type: OutputType) -> ObnizResult<()> {
+        let output_type = push_pull(self.pin(), output_type)?;
+        self.pin.set_output_type(output_type.clone()).await?;
+        self.config.output_type = Some(output_type);
+        Ok(())
+    }
+
+    pub async fn into_input(self, pull_type: PullType) -> ObnizResult<InputPin<T>> {
+        self.pin.into_input(pull_type).await
+    }
+
+    pub async fn into_open_drain(self, pull_type: PullType) -> ObnizResult<OpenDrainPin<T>> {
+        self.pin.into_open_drain(pull_type).await
+    }
+
+    /// Untyped handle, for operations the type-state API does not cover
+    pub fn into_pin(self) -> IoPin<T> {
+        self.pin
+    }
+}
+
+impl<T: ObnizTransport> InputPin<T> {
+    pub fn pin(&self) -> u8 {
+        self.pin.pin()
+    }
+
+    /// Configuration currently applied to the pin
+    pub fn config(&self) -> &IoConfig {
+        &self.config
+    }
+
+    pub async fn get(&self) -> ObnizResult<bool> {
+        self.pin.get().await
+    }
+
+    pub async fn is_high(&self) -> ObnizResult<bool> {
+        self.get().await
+    }
+
+    pub async fn is_low(&self) -> ObnizResult<bool> {
+        Ok(!self.get().await?)
+    }
+
+    pub async fn set_pull_type(&mut self, pull_type: PullType) -> ObnizResult<()> {
+        self.pin.set_pull_type(pull_type.clone()).await?;
+        self.config.pull_type = Some(pull_type);
+        Ok(())
+    }
+
+    /// Stream the pin state to `callback` until the subscription is dropped
+    pub async fn on_change<F>(&mut self, callback: F) -> ObnizResult<Subscription>
+    where
+        F: Fn(bool) + Send + Sync + 'static,
+    {
+        let subscription = self.pin.on_change(callback).await?;
+        self.config.stream = Some(true);
+        Ok(subscription)
+    }
+
+    /// Pin states as a stream; see `IoPin::changes`
+    pub async fn changes(&mut self) -> ObnizResult<InputStream<bool>> {
+        let changes = self.pin.changes().await?;
+        self.config.stream = Some(true);
+        Ok(changes)
+    }
+
+    /// Debounced edges; see `IoPin::on_edge`
+    pub async fn on_edge<F>(
+        &mut self,
+        edge: Edge,
+        debounce: Duration,
+        callback: F,
+    ) -> ObnizResult<Subscription>
+    where
+        F: Fn(bool) + Send + Sync + 'static,
+    {
+        let subscription = self.pin.on_edge(edge, debounce, callback).await?;
+        self.config.stream = Some(true);
+        Ok(subscription)
+    }
+
+    /// Pulse counter; see `IoPin::counter`
+    pub async fn counter(&mut self, config: CounterConfig) -> ObnizResult<IoCounter> {
+        let counter = self.pin.counter(config).await?;
+        self.config.stream = Some(true);
+        Ok(counter)
+    }
+
+    pub async fn into_output(self, output_type: OutputType) -> ObnizResult<OutputPin<T>> {
+        self.pin.into_output(output_type).await
+    }
+
+    pub async fn into_open_drain(self, pull_type: PullType) -> ObnizResult<OpenDrainPin<T>> {
+        self.pin.into_open_drain(pull_type).await
+    }
+
+    /// Untyped handle, for operations the type-state API does not cover
+    pub fn into_pin(self) -> IoPin<T> {
+        self.pin
+    }
+}
+
+impl<T: ObnizTransport> OpenDrainPin<T> {
+    pub fn pin(&self) -> u8 {
+        self.pin.pin()
+    }
+
+    /// Configuration currently applied to the pin
+    pub fn config(&self) -> &IoConfig {
+        &self.config
+    }
+
+    /// Release the line (`true`) or drive it low (`false`)
+    pub async fn set(&mut self, value: bool) -> ObnizResult<()> {
+        self.pin.set(value).await?;
+        self.config.value = Some(value);
+        Ok(())
+    }
+
+    pub async fn release(&mut self) -> ObnizResult<()> {
+        self.set(true).await
+    }
+
+    pub async fn drive_low(&mut self) -> ObnizResult<()> {
+        self.set(false).await
+    }
+
+    /// Level on the line, which another device may be holding low
+    pub async fn get(&self) -> ObnizResult<bool> {
+        self.pin.get().await
+    }
+
+    pub async fn set_pull_type(&mut self, pull_type: PullType) -> ObnizResult<()> {
+        self.pin.set_pull_type(pull_type.clone()).await?;
+        self.config.pull_type = Some(pull_type);
+        Ok(())
+    }
+
+    pub async fn into_output(self, output_type: OutputType) -> ObnizResult<OutputPin<T>> {
+        self.pin.into_output(output_type).await
+    }
+
+    pub async fn into_input(self, pull_type: PullType) -> ObnizResult<InputPin<T>> {
+        self.pin.into_input(pull_type).await
+    }
+
+    /// Untyped handle, for operations the type-state API does not cover
+    pub fn into_pin(self) -> IoPin<T> {
+        self.pin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::IoManager;
+    use crate::mock::{MockConfig, MockObniz};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_output_pin_rejects_open_drain() {
+        let mock = MockObniz::new(MockConfig::default());
+        let io = IoManager::new(mock.clone());
+
+        assert!(matches!(
+            io.pin(0).unwrap().into_output(OutputType::OpenDrain).await,
+            Err(ObnizError::IoOperation(_))
+        ));
+        let mut led = io
+            .pin(0)
+            .unwrap()
+            .into_output(OutputType::PushPull5v)
+            .await
+            .unwrap();
+        assert!(matches!(
+            led.set_output_type(OutputType::OpenDrain).await,
+            Err(ObnizError::IoOperation(_))
+        ));
+        assert_eq!(led.config().output_type, Some(OutputType::PushPull5v));
+        assert_eq!(
+            mock.server().get_sent_messages(),
+            vec![
+                json!([{"io0": {"direction": "output", "value": false, "output_type": "push-pull5v"}}])
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_type_state_pins_remember_config() {
+        let mock = MockObniz::new(MockConfig::default());
+        let io = IoManager::new(mock.clone());
+
+        let mut led = io
+            .pin(0)
+            .unwrap()
+            .into_output(OutputType::PushPull3v)
+            .await
+            .unwrap();
+        led.toggle().await.unwrap();
+        led.toggle().await.unwrap();
+        led.set_high().await.unwrap();
+        assert_eq!(led.config().value, Some(true));
+        assert_eq!(led.config().output_type, Some(OutputType::PushPull3v));
+
+        let mut button = led.into_input(PullType::PullUp3v).await.unwrap();
+        assert_eq!(button.config().direction, Direction::Input);
+        button.set_pull_type(PullType::PullDown).await.unwrap();
+        assert_eq!(button.config().pull_type, Some(PullType::PullDown));
+        assert!(!button.get().await.unwrap());
+
+        let mut bus_line = button.into_open_drain(PullType::PullUp5v).await.unwrap();
+        bus_line.drive_low().await.unwrap();
+        assert_eq!(bus_line.config().output_type, Some(OutputType::OpenDrain));
+
+        assert_eq!(
+            mock.server().get_sent_messages(),
+            vec![
+                json!([{"io0": {"direction": "output", "value": false, "output_type": "push-pull3v"}}]),
+                json!([{"io0": true}]),
+                json!([{"io0": false}]),
+                json!([{"io0": true}]),
+                json!([{"io0": {"direction": "input", "pull_type": "pull-up3v", "stream": false}}]),
+                json!([{"io0": {"pull_type": "pull-down"}}]),
+                json!([{"io0": "get"}]),
+                json!([{"io0": {"direction": "output", "value": true, "output_type": "open-drain", "pull_type": "pull-up5v"}}]),
+                json!([{"io0": false}]),
+            ]
+        );
+    }
+}
@@ -1,9 +1,13 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use tokio_tungstenite::tungstenite::protocol::Message;
 
 use crate::error::{ObnizError, ObnizResult};
 use crate::obniz::Obniz;
+use crate::resources::PinClaim;
+use crate::transport::ObnizTransport;
 
 /// PWM modulation types
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -30,69 +34,113 @@ pub struct ModulationConfig {
 
 /// Individual PWM channel controller
 #[derive(Debug)]
-pub struct PwmChannel {
+pub struct PwmChannel<T: ObnizTransport = Obniz> {
     channel: u8,
-    obniz: Obniz,
+    obniz: T,
+    /// Last frequency set through this handle, 0 if none
+    frequency: AtomicU32,
+    /// Handle of a `PwmAllocation`, the only one that may init or deinit its channel
+    allocated: bool,
 }
 
-impl PwmChannel {
-    pub fn new(channel: u8, obniz: Obniz) -> Self {
-        Self { channel, obniz }
+impl<T: ObnizTransport> PwmChannel<T> {
+    pub fn new(channel: u8, obniz: T) -> Self {
+        Self {
+            channel,
+            obniz,
+            frequency: AtomicU32::new(0),
+            allocated: false,
+        }
+    }
+
+    /// Frequency last set through this handle
+    pub fn frequency(&self) -> Option<u32> {
+        Some(self.frequency.load(Ordering::Relaxed)).filter(|frequency| *frequency != 0)
     }
 
     pub fn channel_key(&self) -> String {
         format!("pwm{}", self.channel)
     }
 
-    /// Initialize PWM channel with IO pin
-    pub async fn init(&self, io_pin: u8) -> ObnizResult<()> {
-        if self.channel > 5 {
-            return Err(ObnizError::Generic("PWM channel must be 0-5".to_string()));
-        }
+    #[cfg(feature = "embedded-hal")]
+    pub(crate) fn transport(&self) -> &T {
+        &self.obniz
+    }
 
-        if io_pin > 11 {
-            return Err(ObnizError::InvalidPin(io_pin));
+    /// Fail with `ChannelInUse` if a live allocation owns the channel and this handle is not it
+    fn check_access(&self) -> ObnizResult<()> {
+        if self.allocated {
+            return Ok(());
         }
+        self.obniz
+            .resources()
+            .check_unallocated(&self.channel_key())
+    }
 
-        let channel_key = self.channel_key();
-        let request = json!([{&channel_key: {"io": io_pin}}]);
+    /// Validate the pins; the claim moving the channel to `io_pin`
+    pub(crate) fn init_claim(&self, io_pin: u8) -> ObnizResult<PinClaim> {
+        let hardware = self.obniz.hardware_profile();
+        hardware.check_pwm(self.channel)?;
+        hardware.check_io(io_pin)?;
+        Ok(PinClaim::exact(&self.channel_key(), &[io_pin]).by_allocation(self.allocated))
+    }
+
+    /// Command object for `init`, e.g. `{"pwm0": {"io": 2}}`; `io_pin` is checked
+    /// for the channel, and taken once the command is sent
+    pub fn init_command(&self, io_pin: u8) -> ObnizResult<Value> {
+        self.obniz.resources().check(&[self.init_claim(io_pin)?])?;
+        Ok(json!({self.channel_key(): {"io": io_pin}}))
+    }
+
+    /// Initialize PWM channel with IO pin
+    pub async fn init(&self, io_pin: u8) -> ObnizResult<()> {
+        let claim = self.init_claim(io_pin)?;
+        let request = json!([{self.channel_key(): {"io": io_pin}}]);
         let message = Message::from(request.to_string());
 
         self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+            .resources()
+            .with_claims(&[claim], || self.obniz.send_message(message))
     }
 
-    /// Set PWM frequency (1 Hz to 80,000,000 Hz)
-    pub async fn set_frequency(&self, frequency: u32) -> ObnizResult<()> {
+    /// Command object for `set_frequency`, e.g. `{"pwm0": {"freq": 1000}}`
+    pub fn frequency_command(&self, frequency: u32) -> ObnizResult<Value> {
         if frequency == 0 || frequency > 80_000_000 {
             return Err(ObnizError::Generic(
                 "Frequency must be between 1 and 80,000,000 Hz".to_string(),
             ));
         }
+        self.check_access()?;
 
-        let channel_key = self.channel_key();
-        let request = json!([{&channel_key: {"freq": frequency}}]);
+        Ok(json!({self.channel_key(): {"freq": frequency}}))
+    }
+
+    /// Set PWM frequency (1 Hz to 80,000,000 Hz)
+    pub async fn set_frequency(&self, frequency: u32) -> ObnizResult<()> {
+        let request = json!([self.frequency_command(frequency)?]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)?;
+        self.frequency.store(frequency, Ordering::Relaxed);
+        Ok(())
     }
 
-    /// Set pulse width in milliseconds
-    pub async fn set_pulse_width(&self, pulse_width_ms: f64) -> ObnizResult<()> {
+    /// Command object for `set_pulse_width`, e.g. `{"pwm0": {"pulse": 0.5}}`
+    pub fn pulse_width_command(&self, pulse_width_ms: f64) -> ObnizResult<Value> {
         if pulse_width_ms < 0.0 {
             return Err(ObnizError::Generic("Pulse width must be >= 0".to_string()));
         }
+        self.check_access()?;
 
-        let channel_key = self.channel_key();
-        let request = json!([{&channel_key: {"pulse": pulse_width_ms}}]);
+        Ok(json!({self.channel_key(): {"pulse": pulse_width_ms}}))
+    }
+
+    /// Set pulse width in milliseconds
+    pub async fn set_pulse_width(&self, pulse_width_ms: f64) -> ObnizResult<()> {
+        let request = json!([self.pulse_width_command(pulse_width_ms)?]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Set duty cycle as percentage (0.0 to 100.0)
@@ -141,6 +189,7 @@ impl PwmChannel {
             }
         }
 
+        self.check_access()?;
         let channel_key = self.channel_key();
         let request = json!([{&channel_key: {
             "modulate": {
@@ -151,9 +200,7 @@ impl PwmChannel {
         }}]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Generate square wave with 50% duty cycle
@@ -187,35 +234,100 @@ impl PwmChannel {
 
     /// Deinitialize PWM channel
     pub async fn deinit(&self) -> ObnizResult<()> {
+        self.check_access()?;
         let channel_key = self.channel_key();
         let request = json!([{&channel_key: null}]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)?;
+        self.obniz.resources().release(&channel_key);
+        Ok(())
+    }
+}
+
+/// PWM channel picked by `PwmManager::allocate`; dropping it deinitializes the
+/// channel and frees it and its pin for the next allocation
+#[derive(Debug)]
+pub struct PwmAllocation<T: ObnizTransport = Obniz> {
+    channel: PwmChannel<T>,
+}
+
+impl<T: ObnizTransport> PwmAllocation<T> {
+    /// Number of the allocated channel
+    pub fn channel(&self) -> u8 {
+        self.channel.channel
+    }
+}
+
+impl<T: ObnizTransport> std::ops::Deref for PwmAllocation<T> {
+    type Target = PwmChannel<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.channel
+    }
+}
+
+impl<T: ObnizTransport> std::ops::DerefMut for PwmAllocation<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.channel
+    }
+}
+
+impl<T: ObnizTransport> Drop for PwmAllocation<T> {
+    fn drop(&mut self) {
+        let channel_key = self.channel.channel_key();
+        let request = json!([{&channel_key: null}]);
+        let _ = self
+            .channel
+            .obniz
+            .send_message(Message::from(request.to_string()));
+        self.channel
+            .obniz
+            .resources()
+            .release_allocation(&channel_key);
     }
 }
 
 /// PWM manager for handling multiple channels
 #[derive(Debug, Clone)]
-pub struct PwmManager {
-    obniz: Obniz,
+pub struct PwmManager<T: ObnizTransport = Obniz> {
+    obniz: T,
 }
 
-impl PwmManager {
-    pub fn new(obniz: Obniz) -> Self {
+impl<T: ObnizTransport> PwmManager<T> {
+    pub fn new(obniz: T) -> Self {
         Self { obniz }
     }
 
-    /// Get specific PWM channel (0-5)
-    pub fn channel(&self, channel: u8) -> ObnizResult<PwmChannel> {
-        if channel > 5 {
-            return Err(ObnizError::Generic("PWM channel must be 0-5".to_string()));
-        }
+    /// Get specific PWM channel (0-5 on the obniz Board)
+    pub fn channel(&self, channel: u8) -> ObnizResult<PwmChannel<T>> {
+        self.obniz.hardware_profile().check_pwm(channel)?;
         Ok(PwmChannel::new(channel, self.obniz.clone()))
     }
 
+    /// Pick a free channel and attach it to `io_pin`
+    pub async fn allocate(&self, io_pin: u8) -> ObnizResult<PwmAllocation<T>> {
+        let hardware = self.obniz.hardware_profile();
+        hardware.check_io(io_pin)?;
+        let channel = self
+            .obniz
+            .resources()
+            .allocate("pwm", hardware.pwm_channels, &[io_pin])?;
+
+        let channel = PwmChannel {
+            allocated: true,
+            ..PwmChannel::new(channel, self.obniz.clone())
+        };
+        // The channel was never initialized, so free it without sending a deinit
+        if let Err(e) = channel.init(io_pin).await {
+            self.obniz
+                .resources()
+                .release_allocation(&channel.channel_key());
+            return Err(e);
+        }
+        Ok(PwmAllocation { channel })
+    }
+
     /// Configure PWM channel
     pub async fn configure_channel(&self, channel: u8, config: PwmConfig) -> ObnizResult<()> {
         self.channel(channel)?.configure(config).await
@@ -262,16 +374,19 @@ impl PwmManager {
         self.channel(channel)?.deinit().await
     }
 
-    /// Deinitialize all PWM channels
+    /// Deinitialize every PWM channel of the board, returning the first failure
+    /// after trying them all
     pub async fn deinit_all(&self) -> ObnizResult<()> {
-        for channel in 0..=5 {
-            if let Err(e) = self.deinit_channel(channel).await {
-                eprintln!("Failed to deinitialize PWM channel {channel}: {e}");
-            }
+        let mut result = Ok(());
+        for channel in 0..self.obniz.hardware_profile().pwm_channels {
+            let deinit = self.deinit_channel(channel).await;
+            result = result.and(deinit);
         }
-        Ok(())
+        result
     }
+}
 
+impl PwmManager {
     /// Utility function to calculate pulse width from duty cycle
     pub fn duty_cycle_to_pulse_width(frequency: u32, duty_percent: f64) -> f64 {
         let period_ms = 1000.0 / frequency as f64;
@@ -288,6 +403,9 @@ impl PwmManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock::{MockConfig, MockObniz};
+    use crate::sequence::Sequence;
+    use crate::uart::UartManager;
 
     #[test]
     fn test_pwm_config_creation() {
@@ -340,4 +458,99 @@ mod tests {
         assert_eq!(format!("pwm{}", 0), "pwm0");
         assert_eq!(format!("pwm{}", 5), "pwm5");
     }
+
+    #[tokio::test]
+    async fn test_commands_on_allocated_channel_are_rejected() {
+        let mock = MockObniz::new(MockConfig::default());
+        let pwm = PwmManager::new(mock.clone());
+        let allocation = pwm.allocate(2).await.unwrap();
+
+        let plain = pwm.channel(0).unwrap();
+        assert!(matches!(
+            plain.set_frequency(50).await,
+            Err(ObnizError::ChannelInUse { channel }) if channel == "pwm0"
+        ));
+        assert!(matches!(
+            plain.set_pulse_width(1.0).await,
+            Err(ObnizError::ChannelInUse { .. })
+        ));
+        assert_eq!(plain.frequency(), None);
+        allocation.set_frequency(50).await.unwrap();
+
+        assert_eq!(
+            mock.server().get_sent_messages(),
+            vec![
+                json!([{"pwm0": {"io": 2}}]),
+                json!([{"pwm0": {"freq": 50}}]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_channel_allocation_guards() {
+        let mock = MockObniz::new(MockConfig::default());
+        let pwm = PwmManager::new(mock.clone());
+
+        let first = pwm.allocate(2).await.unwrap();
+        let second = pwm.allocate(3).await.unwrap();
+        assert_eq!((first.channel(), second.channel()), (0, 1));
+        second.set_frequency(50).await.unwrap();
+        assert!(matches!(
+            pwm.allocate(2).await,
+            Err(ObnizError::PinInUse { pin: 2, .. })
+        ));
+
+        drop(first);
+        assert_eq!(pwm.allocate(4).await.unwrap().channel(), 0);
+
+        assert_eq!(
+            mock.server().get_sent_messages(),
+            vec![
+                json!([{"pwm0": {"io": 2}}]),
+                json!([{"pwm1": {"io": 3}}]),
+                json!([{"pwm1": {"freq": 50}}]),
+                json!([{"pwm0": null}]),
+                json!([{"pwm0": {"io": 4}}]),
+                json!([{"pwm0": null}]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_allocated_channel_rejects_other_handles() {
+        let mock = MockObniz::new(MockConfig::default());
+        let pwm = PwmManager::new(mock.clone());
+        let allocation = pwm.allocate(2).await.unwrap();
+
+        let plain = pwm.channel(0).unwrap();
+        assert!(matches!(
+            plain.init(5).await,
+            Err(ObnizError::ChannelInUse { channel }) if channel == "pwm0"
+        ));
+        assert!(matches!(
+            plain.deinit().await,
+            Err(ObnizError::ChannelInUse { .. })
+        ));
+        assert!(Sequence::new(mock.clone()).pwm_init(0, 5).build().is_err());
+        assert_eq!(mock.resources().pins("pwm0"), vec![2]);
+
+        // The allocation itself may move its channel
+        allocation.init(5).await.unwrap();
+        assert_eq!(mock.resources().pins("pwm0"), vec![5]);
+        drop(allocation);
+        plain.init(5).await.unwrap();
+
+        let uart = UartManager::new(mock.clone());
+        let serial = uart
+            .allocate(UartManager::simple_config(6, 7, 9600))
+            .await
+            .unwrap();
+        assert!(matches!(
+            uart.init_channel(0, UartManager::simple_config(8, 9, 9600))
+                .await,
+            Err(ObnizError::ChannelInUse { .. })
+        ));
+        assert_eq!(mock.resources().pins("uart0"), vec![6, 7]);
+        drop(serial);
+    }
 }
@@ -0,0 +1,256 @@
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+
+/// Reconnect policy with exponential backoff and jitter
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Delay before the first reconnect attempt
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between attempts
+    pub max_delay: Duration,
+    /// Factor applied to the delay after every failed attempt
+    pub multiplier: f64,
+    /// Random jitter as a fraction of the delay (0.0 - 1.0)
+    pub jitter: f64,
+    /// Give up after this many attempts (None = retry forever)
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Backoff delay for a given attempt (0-based) without jitter
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt.min(64) as i32);
+        let delay = self.initial_delay.as_secs_f64() * factor;
+        Duration::from_secs_f64(delay.min(self.max_delay.as_secs_f64()))
+    }
+
+    /// Backoff delay for a given attempt (0-based) with jitter applied
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.base_delay(attempt);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        base.mul_f64(1.0 - jitter * random_unit())
+    }
+
+    /// Check whether another attempt is allowed
+    pub fn should_retry(&self, attempt: u32) -> bool {
+        match self.max_attempts {
+            Some(max) => attempt < max,
+            None => true,
+        }
+    }
+}
+
+/// Random number in [0, 1) without pulling in an RNG crate
+fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Last configuration sent to each IO pin, AD channel, PWM channel and UART.
+/// Replayed to the device after a reconnect.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStateCache {
+    entries: BTreeMap<String, Vec<Map<String, Value>>>,
+}
+
+impl DeviceStateCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an outgoing frame (e.g. `[{"io0": true}, {"pwm0": {"freq": 1000}}]`)
+    pub fn record(&mut self, frame: &Value) {
+        let items = match frame.as_array() {
+            Some(array) => array.iter().collect::<Vec<_>>(),
+            None => vec![frame],
+        };
+
+        for item in items {
+            if let Some(obj) = item.as_object() {
+                for (key, value) in obj {
+                    if is_replayable_key(key) {
+                        self.record_command(key, value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn record_command(&mut self, key: &str, value: &Value) {
+        let command = match value {
+            // Deinit clears everything we know about the module
+            Value::Null => {
+                self.entries.remove(key);
+                return;
+            }
+            // `ioX: true/false` drives the pin as output
+            Value::Bool(state) if key.starts_with("io") => {
+                let mut map = Map::new();
+                map.insert("direction".to_string(), json!("output"));
+                map.insert("value".to_string(), json!(state));
+                map
+            }
+            Value::Object(obj) => obj
+                .iter()
+                .filter(|(field, _)| !is_transient_field(key, field))
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect(),
+            // "get" requests and anything else do not change device state
+            _ => return,
+        };
+
+        if command.is_empty() {
+            return;
+        }
+
+        let history = self.entries.entry(key.to_string()).or_default();
+        // Newer commands override the same fields of older ones
+        for previous in history.iter_mut() {
+            previous.retain(|field, _| !command.contains_key(field));
+        }
+        history.retain(|previous| !previous.is_empty());
+        history.push(command);
+    }
+
+    /// Build a single frame that restores the recorded state
+    pub fn replay_frame(&self) -> Option<Value> {
+        let commands: Vec<Value> = self
+            .entries
+            .iter()
+            .flat_map(|(key, history)| history.iter().map(move |command| json!({ key: command })))
+            .collect();
+
+        if commands.is_empty() {
+            None
+        } else {
+            Some(Value::Array(commands))
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+fn is_replayable_key(key: &str) -> bool {
+    ["io", "ad", "pwm", "uart"].iter().any(|prefix| {
+        key.strip_prefix(prefix)
+            .is_some_and(|rest| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit()))
+    })
+}
+
+/// Fields that trigger a one-off action rather than configure the module
+fn is_transient_field(key: &str, field: &str) -> bool {
+    (key.starts_with("uart") && field == "data") || (key.starts_with("pwm") && field == "modulate")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            multiplier: 2.0,
+            jitter: 0.0,
+            max_attempts: None,
+        };
+
+        assert_eq!(policy.base_delay(0), Duration::from_millis(100));
+        assert_eq!(policy.base_delay(1), Duration::from_millis(200));
+        assert_eq!(policy.base_delay(3), Duration::from_millis(800));
+        assert_eq!(policy.base_delay(4), Duration::from_millis(1000));
+        assert_eq!(policy.base_delay(100), Duration::from_millis(1000));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_jitter_stays_within_bounds() {
+        let policy = ReconnectPolicy {
+            jitter: 0.5,
+            ..Default::default()
+        };
+
+        for attempt in 0..20 {
+            let base = policy.base_delay(attempt);
+            let delay = policy.delay(attempt);
+            assert!(delay <= base);
+            assert!(delay >= base.mul_f64(0.5));
+        }
+    }
+
+    #[test]
+    fn test_max_attempts() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(3),
+            ..Default::default()
+        };
+        assert!(policy.should_retry(2));
+        assert!(!policy.should_retry(3));
+        assert!(ReconnectPolicy::default().should_retry(u32::MAX));
+    }
+
+    #[test]
+    fn test_state_cache_merges_commands() {
+        let mut cache = DeviceStateCache::new();
+        cache.record(&json!([{"pwm0": {"io": 2}}]));
+        cache.record(&json!([{"pwm0": {"freq": 1000}}]));
+        cache.record(&json!([{"pwm0": {"pulse": 0.5}}]));
+        cache.record(&json!([{"pwm0": {"freq": 50}}]));
+        cache.record(&json!([{"io1": true}]));
+        cache.record(&json!([{"io1": false}]));
+        cache.record(&json!([{"io2": "get"}]));
+
+        assert_eq!(
+            cache.replay_frame().unwrap(),
+            json!([
+                {"io1": {"direction": "output", "value": false}},
+                {"pwm0": {"io": 2}},
+                {"pwm0": {"pulse": 0.5}},
+                {"pwm0": {"freq": 50}}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_state_cache_skips_transient_and_deinit() {
+        let mut cache = DeviceStateCache::new();
+        cache.record(&json!([{"uart0": {"rx": 0, "tx": 1, "baud": 9600}}]));
+        cache.record(&json!([{"uart0": {"data": [1, 2, 3]}}]));
+        cache.record(&json!([{"ad3": {"stream": true}}]));
+        cache.record(&json!([{"display": {"text": "hello"}}]));
+        cache.record(&json!([{"ad3": null}]));
+
+        assert_eq!(
+            cache.replay_frame().unwrap(),
+            json!([{"uart0": {"rx": 0, "tx": 1, "baud": 9600}}])
+        );
+
+        cache.clear();
+        assert!(cache.is_empty());
+        assert!(cache.replay_frame().is_none());
+    }
+}
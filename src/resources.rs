@@ -0,0 +1,501 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::error::{ObnizError, ObnizResult};
+
+/// Pins a command takes for `owner` once it is sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinClaim {
+    owner: String,
+    pins: Vec<u8>,
+    /// Free the pins `owner` held before, as when a channel moves to new pins
+    exact: bool,
+    /// Made by the allocation holding `owner`
+    by_allocation: bool,
+}
+
+impl PinClaim {
+    /// Add `pins` to those `owner` already holds
+    pub fn new(owner: &str, pins: &[u8]) -> Self {
+        Self {
+            owner: owner.to_string(),
+            pins: pins.to_vec(),
+            exact: false,
+            by_allocation: false,
+        }
+    }
+
+    /// Give `owner` exactly `pins`, freeing any others it holds
+    pub fn exact(owner: &str, pins: &[u8]) -> Self {
+        Self {
+            exact: true,
+            ..Self::new(owner, pins)
+        }
+    }
+
+    /// Mark the claim as coming from the allocation that holds the channel
+    pub(crate) fn by_allocation(mut self, by_allocation: bool) -> Self {
+        self.by_allocation = by_allocation;
+        self
+    }
+
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn pins(&self) -> &[u8] {
+        &self.pins
+    }
+}
+
+#[derive(Debug, Default)]
+struct Tables {
+    /// Owner of each claimed pin
+    pins: HashMap<u8, String>,
+    /// Channels (`pwm0`, `uart1`) held by a live `allocate` result
+    allocated: HashSet<String>,
+}
+
+/// Pin changes made by one `take`, undone in reverse order
+type Undo = Vec<(u8, Option<String>)>;
+
+impl Tables {
+    /// Apply `claims` in order, recording what they replaced; on failure
+    /// everything applied so far is undone again
+    fn apply(&mut self, claims: &[PinClaim]) -> ObnizResult<Undo> {
+        let mut undo = Undo::new();
+        for claim in claims {
+            if let Err(e) = self.apply_one(claim, &mut undo) {
+                self.undo(undo);
+                return Err(e);
+            }
+        }
+        Ok(undo)
+    }
+
+    fn apply_one(&mut self, claim: &PinClaim, undo: &mut Undo) -> ObnizResult<()> {
+        if !claim.by_allocation && self.allocated.contains(&claim.owner) {
+            return Err(ObnizError::ChannelInUse {
+                channel: claim.owner.clone(),
+            });
+        }
+        check_free(&self.pins, &claim.owner, &claim.pins)?;
+
+        if claim.exact {
+            let stale: Vec<u8> = self
+                .pins
+                .iter()
+                .filter(|(pin, owner)| **owner == claim.owner && !claim.pins.contains(pin))
+                .map(|(pin, _)| *pin)
+                .collect();
+            for pin in stale {
+                undo.push((pin, self.pins.remove(&pin)));
+            }
+        }
+        for pin in &claim.pins {
+            if self.pins.get(pin) != Some(&claim.owner) {
+                undo.push((*pin, self.pins.insert(*pin, claim.owner.clone())));
+            }
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self, undo: Undo) {
+        for (pin, previous) in undo.into_iter().rev() {
+            match previous {
+                Some(owner) => self.pins.insert(pin, owner),
+                None => self.pins.remove(&pin),
+            };
+        }
+    }
+}
+
+/// Which module (`io0`, `ad3`, `pwm1`, `uart0`, ...) owns each IO pin, and which
+/// channels are held by an allocation.
+/// Clones share the same table, so every handle of one `Obniz` sees the same owners.
+#[derive(Debug, Clone, Default)]
+pub struct ResourceRegistry {
+    tables: Arc<Mutex<Tables>>,
+}
+
+impl ResourceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `pins` to those `owner` holds.
+    /// Nothing changes if one of the pins belongs to another module.
+    pub fn claim(&self, owner: &str, pins: &[u8]) -> ObnizResult<()> {
+        self.take(&[PinClaim::new(owner, pins)]).map(Claimed::keep)
+    }
+
+    /// Check that `claims` could be taken together, without taking them
+    pub fn check(&self, claims: &[PinClaim]) -> ObnizResult<()> {
+        let mut tables = self.tables.lock().unwrap();
+        let undo = tables.apply(claims)?;
+        tables.undo(undo);
+        Ok(())
+    }
+
+    /// Take all of `claims` or none of them. The returned guard keeps them
+    /// unless `rollback` is called, e.g. because the command could not be sent.
+    pub fn take(&self, claims: &[PinClaim]) -> ObnizResult<Claimed> {
+        let undo = self.tables.lock().unwrap().apply(claims)?;
+        Ok(Claimed {
+            registry: self.clone(),
+            undo,
+        })
+    }
+
+    /// Take `claims`, run `send`, and give the pins back if it fails
+    pub fn with_claims<R>(
+        &self,
+        claims: &[PinClaim],
+        send: impl FnOnce() -> ObnizResult<R>,
+    ) -> ObnizResult<R> {
+        let claimed = self.take(claims)?;
+        match send() {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                claimed.rollback();
+                Err(e)
+            }
+        }
+    }
+
+    /// Hold the first free channel `{prefix}0` .. `{prefix}{count - 1}` with `pins`
+    /// and return its number; picking and claiming happen under one lock.
+    /// Until `release_allocation`, other handles cannot claim or release the channel.
+    pub fn allocate(&self, prefix: &str, count: u8, pins: &[u8]) -> ObnizResult<u8> {
+        let mut tables = self.tables.lock().unwrap();
+        let channel = (0..count)
+            .find(|channel| {
+                let key = format!("{prefix}{channel}");
+                !tables.allocated.contains(&key)
+                    && !tables.pins.values().any(|current| *current == key)
+            })
+            .ok_or_else(|| ObnizError::Generic(format!("All {prefix} channels are in use")))?;
+
+        let owner = format!("{prefix}{channel}");
+        tables.apply(&[PinClaim::exact(&owner, pins)])?;
+        tables.allocated.insert(owner);
+        Ok(channel)
+    }
+
+    /// Free every pin held by `owner`
+    pub fn release(&self, owner: &str) {
+        self.tables
+            .lock()
+            .unwrap()
+            .pins
+            .retain(|_, current| current != owner);
+    }
+
+    /// Free an allocated channel and its pins
+    pub fn release_allocation(&self, channel: &str) {
+        let mut tables = self.tables.lock().unwrap();
+        tables.allocated.remove(channel);
+        tables.pins.retain(|_, current| current != channel);
+    }
+
+    /// Check whether a live allocation holds `channel`
+    pub fn is_allocated(&self, channel: &str) -> bool {
+        self.tables.lock().unwrap().allocated.contains(channel)
+    }
+
+    /// Fail with `ChannelInUse` if an allocation holds `channel`
+    pub fn check_unallocated(&self, channel: &str) -> ObnizResult<()> {
+        if self.is_allocated(channel) {
+            return Err(ObnizError::ChannelInUse {
+                channel: channel.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Module currently holding `pin`
+    pub fn owner(&self, pin: u8) -> Option<String> {
+        self.tables.lock().unwrap().pins.get(&pin).cloned()
+    }
+
+    /// Pins held by `owner`, in ascending order
+    pub fn pins(&self, owner: &str) -> Vec<u8> {
+        let mut pins: Vec<u8> = self
+            .tables
+            .lock()
+            .unwrap()
+            .pins
+            .iter()
+            .filter(|(_, current)| current.as_str() == owner)
+            .map(|(pin, _)| *pin)
+            .collect();
+        pins.sort_unstable();
+        pins
+    }
+
+    /// Check whether a module or peripheral channel (e.g. `pwm0`) holds any pin
+    pub fn is_claimed(&self, owner: &str) -> bool {
+        self.tables
+            .lock()
+            .unwrap()
+            .pins
+            .values()
+            .any(|current| current == owner)
+    }
+
+    /// Forget all owners and allocations
+    pub fn clear(&self) {
+        let mut tables = self.tables.lock().unwrap();
+        tables.pins.clear();
+        tables.allocated.clear();
+    }
+}
+
+/// Claims taken by `ResourceRegistry::take`; dropping the guard keeps them
+#[must_use = "dropping Claimed keeps the claims; call rollback to undo them"]
+#[derive(Debug)]
+pub struct Claimed {
+    registry: ResourceRegistry,
+    undo: Undo,
+}
+
+impl Claimed {
+    /// Keep the claims
+    pub fn keep(self) {}
+
+    /// Give the pins back to their previous owners
+    pub fn rollback(self) {
+        self.registry.tables.lock().unwrap().undo(self.undo);
+    }
+}
+
+/// Fail with `PinInUse` if a module other than `owner` holds one of `pins`
+fn check_free(owners: &HashMap<u8, String>, owner: &str, pins: &[u8]) -> ObnizResult<()> {
+    for pin in pins {
+        match owners.get(pin) {
+            Some(current) if current != owner => {
+                return Err(ObnizError::PinInUse {
+                    pin: *pin,
+                    owner: current.clone(),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::IoManager;
+    use crate::io_animation::IoAnimationManager;
+    use crate::mock::{MockConfig, MockObniz};
+    use crate::pwm::PwmManager;
+    use crate::sequence::Sequence;
+    use crate::transport::ObnizTransport;
+    use crate::uart::UartManager;
+
+    #[test]
+    fn test_claim_conflicts_and_release() {
+        let registry = ResourceRegistry::new();
+        registry.claim("uart0", &[0, 1]).unwrap();
+        assert_eq!(registry.owner(1), Some("uart0".to_string()));
+
+        assert!(matches!(
+            registry.claim("pwm0", &[2, 1]),
+            Err(ObnizError::PinInUse { pin: 1, owner }) if owner == "uart0"
+        ));
+        // A failed claim takes nothing
+        assert_eq!(registry.owner(2), None);
+
+        registry.release("uart0");
+        assert!(!registry.is_claimed("uart0"));
+        registry.claim("pwm0", &[2, 1]).unwrap();
+        assert_eq!(registry.pins("pwm0"), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_allocate_picks_free_channel() {
+        let registry = ResourceRegistry::new();
+        registry.claim("pwm0", &[0]).unwrap();
+        assert_eq!(registry.allocate("pwm", 2, &[1]).unwrap(), 1);
+        assert!(matches!(
+            registry.allocate("pwm", 2, &[2]),
+            Err(ObnizError::Generic(_))
+        ));
+
+        registry.release("pwm0");
+        assert!(matches!(
+            registry.allocate("pwm", 2, &[1]),
+            Err(ObnizError::PinInUse { pin: 1, .. })
+        ));
+        assert_eq!(registry.allocate("pwm", 2, &[2]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_claims_add_and_exact_claims_move() {
+        let registry = ResourceRegistry::new();
+        registry.claim("animation:blink", &[3]).unwrap();
+        registry.claim("animation:blink", &[4]).unwrap();
+        assert_eq!(registry.pins("animation:blink"), vec![3, 4]);
+
+        registry
+            .take(&[PinClaim::exact("pwm0", &[5])])
+            .unwrap()
+            .keep();
+        registry
+            .take(&[PinClaim::exact("pwm0", &[6])])
+            .unwrap()
+            .keep();
+        assert_eq!(registry.owner(5), None);
+        assert_eq!(registry.pins("pwm0"), vec![6]);
+
+        // Clones share the table
+        let clone = registry.clone();
+        clone.claim("io5", &[5]).unwrap();
+        assert_eq!(registry.owner(5), Some("io5".to_string()));
+        registry.clear();
+        assert_eq!(clone.owner(5), None);
+    }
+
+    #[test]
+    fn test_take_is_all_or_nothing_and_rolls_back() {
+        let registry = ResourceRegistry::new();
+        registry.claim("uart0", &[0, 1]).unwrap();
+        registry
+            .take(&[PinClaim::exact("pwm0", &[2])])
+            .unwrap()
+            .keep();
+
+        // The second claim conflicts, so the first is not kept either
+        assert!(matches!(
+            registry.take(&[PinClaim::new("io3", &[3]), PinClaim::new("io1", &[1])]),
+            Err(ObnizError::PinInUse { pin: 1, .. })
+        ));
+        assert_eq!(registry.owner(3), None);
+
+        // Claims in one batch see each other
+        assert!(registry
+            .check(&[PinClaim::new("io4", &[4]), PinClaim::new("pwm1", &[4])])
+            .is_err());
+        assert_eq!(registry.owner(4), None);
+
+        let claimed = registry
+            .take(&[PinClaim::new("io3", &[3]), PinClaim::exact("pwm0", &[4])])
+            .unwrap();
+        assert_eq!(registry.pins("pwm0"), vec![4]);
+        claimed.rollback();
+        assert_eq!(registry.owner(3), None);
+        assert_eq!(registry.pins("pwm0"), vec![2]);
+
+        let result = registry.with_claims(&[PinClaim::new("io5", &[5])], || -> ObnizResult<()> {
+            Err(ObnizError::Timeout)
+        });
+        assert!(matches!(result, Err(ObnizError::Timeout)));
+        assert!(!registry.is_claimed("io5"));
+    }
+
+    #[test]
+    fn test_allocated_channels_are_off_limits() {
+        let registry = ResourceRegistry::new();
+        assert_eq!(registry.allocate("pwm", 2, &[1]).unwrap(), 0);
+        assert!(registry.is_allocated("pwm0"));
+
+        assert!(matches!(
+            registry.take(&[PinClaim::exact("pwm0", &[2])]),
+            Err(ObnizError::ChannelInUse { channel }) if channel == "pwm0"
+        ));
+        assert!(registry.check_unallocated("pwm0").is_err());
+        registry
+            .take(&[PinClaim::exact("pwm0", &[2]).by_allocation(true)])
+            .unwrap()
+            .keep();
+        assert_eq!(registry.pins("pwm0"), vec![2]);
+
+        // Releasing the pins alone keeps the channel reserved
+        registry.release("pwm0");
+        assert_eq!(registry.allocate("pwm", 2, &[3]).unwrap(), 1);
+        registry.release_allocation("pwm0");
+        assert!(registry.check_unallocated("pwm0").is_ok());
+        assert_eq!(registry.allocate("pwm", 2, &[4]).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_command_builders_check_without_claiming() {
+        let mock = MockObniz::new(MockConfig::default());
+        let io = IoManager::new(mock.clone());
+
+        io.pin(3).unwrap().set_command(true).unwrap();
+        PwmManager::new(mock.clone())
+            .channel(0)
+            .unwrap()
+            .init_command(4)
+            .unwrap();
+        IoAnimationManager::new(mock.clone())
+            .start_command("blink", &IoAnimationManager::blink(5, 100), None)
+            .unwrap();
+        let frame = Sequence::new(mock.clone())
+            .io(6, true)
+            .pwm_init(1, 7)
+            .build();
+        assert!(frame.is_ok());
+        for pin in 3..=7 {
+            assert_eq!(mock.resources().owner(pin), None);
+        }
+
+        // Steps that fight over a pin are caught before anything is taken
+        assert!(matches!(
+            Sequence::new(mock.clone())
+                .io(6, true)
+                .pwm_init(1, 6)
+                .send()
+                .await,
+            Err(ObnizError::PinInUse { pin: 6, .. })
+        ));
+        assert_eq!(mock.resources().owner(6), None);
+
+        Sequence::new(mock.clone())
+            .io(6, true)
+            .pwm_init(1, 7)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(mock.resources().owner(6), Some("io6".to_string()));
+        assert_eq!(mock.resources().pins("pwm1"), vec![7]);
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_pin_use_is_rejected() {
+        let mock = MockObniz::new(MockConfig::default());
+        let pwm = PwmManager::new(mock.clone());
+        let uart = UartManager::new(mock.clone());
+
+        pwm.channel(0).unwrap().init(1).await.unwrap();
+        IoManager::new(mock.clone())
+            .set_pin_as_input(0, true)
+            .await
+            .unwrap();
+
+        let config = UartManager::simple_config(0, 1, 9600);
+        assert!(matches!(
+            uart.init_channel(0, config.clone()).await,
+            Err(ObnizError::PinInUse { pin: 0, owner }) if owner == "io0"
+        ));
+
+        IoManager::new(mock.clone())
+            .pin(0)
+            .unwrap()
+            .deinit()
+            .await
+            .unwrap();
+        assert!(matches!(
+            uart.init_channel(0, config.clone()).await,
+            Err(ObnizError::PinInUse { pin: 1, owner }) if owner == "pwm0"
+        ));
+
+        pwm.deinit_channel(0).await.unwrap();
+        uart.init_channel(0, config).await.unwrap();
+        assert_eq!(mock.resources().pins("uart0"), vec![0, 1]);
+    }
+}
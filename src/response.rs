@@ -0,0 +1,236 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Map, Value};
+
+use crate::error::{ObnizError, ObnizResult};
+use crate::switch::SwitchResponse;
+
+/// A single message from the device, e.g. `{"io3": true}` or `{"ad0": 3.3}`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObnizResponse {
+    /// Pin state (`get` response or stream update)
+    Io { pin: u8, value: bool },
+    /// Voltage of an AD channel
+    Ad { channel: u8, voltage: f64 },
+    /// Bytes received on a UART channel
+    Uart { channel: u8, data: Vec<u8> },
+    /// Switch state change
+    Switch(SwitchResponse),
+    /// `system` response (info, status, pong, ...)
+    System(Value),
+    /// `ws` control message (ready, redirect, ...)
+    Ws(Value),
+    /// `debug` message from the firmware
+    Debug(Value),
+    /// Any other module
+    Unknown { key: String, value: Value },
+}
+
+impl ObnizResponse {
+    /// Parse the value under one key of a message object
+    pub fn parse(key: &str, value: &Value) -> ObnizResult<Self> {
+        let invalid = |reason: &str| ObnizError::InvalidResponse {
+            key: key.to_string(),
+            reason: reason.to_string(),
+        };
+
+        if let Some(pin) = module_index(key, "io") {
+            let value = value
+                .as_bool()
+                .ok_or_else(|| invalid("expected a boolean"))?;
+            return Ok(ObnizResponse::Io { pin, value });
+        }
+        if let Some(channel) = module_index(key, "ad") {
+            let voltage = value.as_f64().ok_or_else(|| invalid("expected a number"))?;
+            return Ok(ObnizResponse::Ad { channel, voltage });
+        }
+        if let Some(channel) = module_index(key, "uart") {
+            let data = value
+                .get("data")
+                .and_then(Value::as_array)
+                .ok_or_else(|| invalid("expected a data array"))?
+                .iter()
+                .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| invalid("data must be bytes"))?;
+            return Ok(ObnizResponse::Uart { channel, data });
+        }
+
+        match key {
+            "switch" => serde_json::from_value(value.clone())
+                .map(ObnizResponse::Switch)
+                .map_err(|e| invalid(&e.to_string())),
+            "system" => Ok(ObnizResponse::System(value.clone())),
+            "ws" => Ok(ObnizResponse::Ws(value.clone())),
+            "debug" => Ok(ObnizResponse::Debug(value.clone())),
+            _ => Ok(ObnizResponse::Unknown {
+                key: key.to_string(),
+                value: value.clone(),
+            }),
+        }
+    }
+
+    /// Parse every key of every object in a frame (`[{...}, ...]` or `{...}`),
+    /// keeping the key next to each result so errors can still be routed
+    pub fn parse_frame(frame: &Value) -> Vec<(String, ObnizResult<Self>)> {
+        let items = match frame.as_array() {
+            Some(items) => items.iter().collect::<Vec<_>>(),
+            None => vec![frame],
+        };
+
+        items
+            .into_iter()
+            .filter_map(Value::as_object)
+            .flat_map(|obj| obj.iter())
+            .map(|(key, value)| (key.clone(), Self::parse(key, value)))
+            .collect()
+    }
+
+    /// Find and parse the message for `key` in a frame
+    pub fn from_frame(frame: &Value, key: &str) -> ObnizResult<Self> {
+        Self::parse_frame(frame)
+            .into_iter()
+            .find(|(item_key, _)| item_key == key)
+            .map(|(_, response)| response)
+            .unwrap_or_else(|| {
+                Err(ObnizError::InvalidResponse {
+                    key: key.to_string(),
+                    reason: "no message for this key".to_string(),
+                })
+            })
+    }
+
+    /// Key the message is routed under, e.g. `io3` or `system`
+    pub fn key(&self) -> String {
+        match self {
+            ObnizResponse::Io { pin, .. } => format!("io{pin}"),
+            ObnizResponse::Ad { channel, .. } => format!("ad{channel}"),
+            ObnizResponse::Uart { channel, .. } => format!("uart{channel}"),
+            ObnizResponse::Switch(_) => "switch".to_string(),
+            ObnizResponse::System(_) => "system".to_string(),
+            ObnizResponse::Ws(_) => "ws".to_string(),
+            ObnizResponse::Debug(_) => "debug".to_string(),
+            ObnizResponse::Unknown { key, .. } => key.clone(),
+        }
+    }
+
+    /// Message object in wire format, e.g. `{"io3": true}`
+    pub fn to_item(&self) -> Value {
+        let value = match self {
+            ObnizResponse::Io { value, .. } => json!(value),
+            ObnizResponse::Ad { voltage, .. } => json!(voltage),
+            ObnizResponse::Uart { data, .. } => json!({ "data": data }),
+            ObnizResponse::Switch(switch) => json!(switch),
+            ObnizResponse::System(value)
+            | ObnizResponse::Ws(value)
+            | ObnizResponse::Debug(value)
+            | ObnizResponse::Unknown { value, .. } => value.clone(),
+        };
+        json!({ self.key(): value })
+    }
+
+    /// Error for a response of the wrong kind
+    pub fn unexpected(self, key: &str) -> ObnizError {
+        ObnizError::InvalidResponse {
+            key: key.to_string(),
+            reason: format!("unexpected response {:?}", self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ObnizResponse {
+    /// Deserialize a single-key message object such as `{"ad0": 3.3}`
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let obj = Map::<String, Value>::deserialize(deserializer)?;
+        let mut entries = obj.iter();
+        match (entries.next(), entries.next()) {
+            (Some((key, value)), None) => Self::parse(key, value).map_err(D::Error::custom),
+            _ => Err(D::Error::custom("expected an object with exactly one key")),
+        }
+    }
+}
+
+/// `io3` -> 3 for prefix `io`
+fn module_index(key: &str, prefix: &str) -> Option<u8> {
+    let index = key.strip_prefix(prefix)?;
+    if index.is_empty() || !index.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    index.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::switch::{SwitchAction, SwitchState};
+
+    #[test]
+    fn test_parse_module_messages() {
+        assert_eq!(
+            ObnizResponse::parse("io3", &json!(true)).unwrap(),
+            ObnizResponse::Io {
+                pin: 3,
+                value: true
+            }
+        );
+        assert_eq!(
+            ObnizResponse::parse("ad0", &json!(3.3)).unwrap(),
+            ObnizResponse::Ad {
+                channel: 0,
+                voltage: 3.3
+            }
+        );
+        assert_eq!(
+            ObnizResponse::parse("uart0", &json!({"data": [104, 105]})).unwrap(),
+            ObnizResponse::Uart {
+                channel: 0,
+                data: b"hi".to_vec()
+            }
+        );
+        assert_eq!(
+            ObnizResponse::parse("switch", &json!({"state": "push", "action": "push"})).unwrap(),
+            ObnizResponse::Switch(SwitchResponse {
+                state: SwitchState::Push,
+                action: SwitchAction::Push
+            })
+        );
+        assert_eq!(
+            ObnizResponse::parse("display", &json!({"state": "ok"})).unwrap(),
+            ObnizResponse::Unknown {
+                key: "display".to_string(),
+                value: json!({"state": "ok"})
+            }
+        );
+        assert!(matches!(
+            ObnizResponse::parse("io", &json!({"animation": {}})),
+            Ok(ObnizResponse::Unknown { .. })
+        ));
+    }
+
+    #[test]
+    fn test_malformed_messages_are_typed_errors() {
+        assert!(matches!(
+            ObnizResponse::parse("io3", &json!("high")),
+            Err(ObnizError::InvalidResponse { key, .. }) if key == "io3"
+        ));
+        assert!(ObnizResponse::parse("ad1", &json!(null)).is_err());
+        assert!(ObnizResponse::parse("uart0", &json!({"data": [300]})).is_err());
+        assert!(ObnizResponse::parse("switch", &json!({"state": "sideways"})).is_err());
+    }
+
+    #[test]
+    fn test_frame_parsing_and_round_trip() {
+        let frame = json!([{"io0": true, "io1": false}, {"system": {"pong": 1}}]);
+        let parsed = ObnizResponse::parse_frame(&frame);
+        assert_eq!(parsed.len(), 3);
+
+        let io1 = ObnizResponse::from_frame(&frame, "io1").unwrap();
+        assert_eq!(io1.key(), "io1");
+        assert_eq!(io1.to_item(), json!({"io1": false}));
+        assert!(ObnizResponse::from_frame(&frame, "ad0").is_err());
+
+        let response: ObnizResponse = serde_json::from_value(json!({"ad2": 1.5})).unwrap();
+        assert_eq!(response.to_item(), json!({"ad2": 1.5}));
+        assert!(serde_json::from_value::<ObnizResponse>(json!({})).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::error::{ObnizError, ObnizResult};
+use crate::io::{IoConfig, IoPin};
+use crate::obniz::Obniz;
+use crate::pwm::PwmManager;
+use crate::resources::PinClaim;
+use crate::transport::ObnizTransport;
+use crate::uart::UartManager;
+
+/// Commands shipped to the device as one frame and executed back-to-back,
+/// with `wait` steps timed by the device instead of the network
+#[derive(Debug)]
+pub struct Sequence<T: ObnizTransport = Obniz> {
+    obniz: T,
+    commands: Vec<Value>,
+    /// Pins the steps take once the sequence is sent
+    claims: Vec<PinClaim>,
+    /// First invalid step, reported by `build`
+    error: Option<ObnizError>,
+}
+
+impl<T: ObnizTransport> Sequence<T> {
+    pub fn new(obniz: T) -> Self {
+        Self {
+            obniz,
+            commands: Vec::new(),
+            claims: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn push(self, command: ObnizResult<Value>) -> Self {
+        self.push_claiming(command.map(|command| (command, None)))
+    }
+
+    fn push_claiming(mut self, step: ObnizResult<(Value, Option<PinClaim>)>) -> Self {
+        match step {
+            Ok((command, claim)) => {
+                self.commands.push(command);
+                self.claims.extend(claim);
+            }
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self
+    }
+
+    /// Drive an IO pin high or low
+    pub fn io(self, pin: u8, value: bool) -> Self {
+        let pin = IoPin::new(pin, self.obniz.clone());
+        let step = pin
+            .set_command(value)
+            .and_then(|command| Ok((command, Some(pin.pin_claim()?))));
+        self.push_claiming(step)
+    }
+
+    /// Configure an IO pin
+    pub fn io_configure(self, pin: u8, config: IoConfig) -> Self {
+        let pin = IoPin::new(pin, self.obniz.clone());
+        let step = pin
+            .configure_command(config)
+            .and_then(|command| Ok((command, Some(pin.pin_claim()?))));
+        self.push_claiming(step)
+    }
+
+    /// Attach a PWM channel to an IO pin
+    pub fn pwm_init(self, channel: u8, io_pin: u8) -> Self {
+        let step = PwmManager::new(self.obniz.clone())
+            .channel(channel)
+            .and_then(|pwm| Ok((pwm.init_command(io_pin)?, Some(pwm.init_claim(io_pin)?))));
+        self.push_claiming(step)
+    }
+
+    /// Change the frequency of a PWM channel
+    pub fn pwm_frequency(self, channel: u8, frequency: u32) -> Self {
+        let command = PwmManager::new(self.obniz.clone())
+            .channel(channel)
+            .and_then(|pwm| pwm.frequency_command(frequency));
+        self.push(command)
+    }
+
+    /// Change the pulse width of a PWM channel
+    pub fn pwm_pulse_width(self, channel: u8, pulse_width_ms: f64) -> Self {
+        let command = PwmManager::new(self.obniz.clone())
+            .channel(channel)
+            .and_then(|pwm| pwm.pulse_width_command(pulse_width_ms));
+        self.push(command)
+    }
+
+    /// Send bytes on a UART channel
+    pub fn uart_send(self, channel: u8, data: &[u8]) -> Self {
+        let command = UartManager::new(self.obniz.clone())
+            .channel(channel)
+            .and_then(|uart| uart.send_command(data));
+        self.push(command)
+    }
+
+    /// Pause on the device before the next step (`system.wait`)
+    pub fn wait(self, ms: u32) -> Self {
+        self.push(Ok(json!({"system": {"wait": ms}})))
+    }
+
+    /// Number of steps collected so far
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Validate the steps, and the pins they take together, without taking them
+    fn validate(&self) -> ObnizResult<()> {
+        if self.commands.is_empty() {
+            return Err(ObnizError::Generic("Sequence is empty".to_string()));
+        }
+        self.obniz.resources().check(&self.claims)
+    }
+
+    /// Validate the steps and build the frame; pins are taken only by `send`
+    pub fn build(self) -> ObnizResult<Value> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.validate()?;
+        Ok(Value::Array(self.commands))
+    }
+
+    /// Send the whole sequence as one frame; nothing is sent or claimed if any step
+    /// is invalid, and the pins are given back if sending fails
+    pub async fn send(self) -> ObnizResult<()> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.validate()?;
+        let message = Message::from(Value::Array(self.commands).to_string());
+        self.obniz
+            .resources()
+            .with_claims(&self.claims, || self.obniz.send_message(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::Direction;
+    use crate::mock::{MockConfig, MockObniz};
+
+    #[test]
+    fn test_sequence_builds_one_frame() {
+        let mock = MockObniz::new(MockConfig::default());
+        let frame = Sequence::new(mock)
+            .io_configure(
+                0,
+                IoConfig {
+                    direction: Direction::Output,
+                    value: Some(false),
+                    output_type: None,
+                    pull_type: None,
+                    stream: None,
+                },
+            )
+            .io(0, true)
+            .wait(5)
+            .io(0, false)
+            .pwm_init(1, 3)
+            .pwm_frequency(1, 50)
+            .pwm_pulse_width(1, 1.5)
+            .uart_send(0, b"hi")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            frame,
+            json!([
+                {"io0": {"direction": "output", "value": false}},
+                {"io0": true},
+                {"system": {"wait": 5}},
+                {"io0": false},
+                {"pwm1": {"io": 3}},
+                {"pwm1": {"freq": 50}},
+                {"pwm1": {"pulse": 1.5}},
+                {"uart0": {"data": [104, 105]}}
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_step_sends_nothing() {
+        let mock = MockObniz::new(MockConfig::default());
+        let result = Sequence::new(mock.clone())
+            .io(0, true)
+            .io(12, true)
+            .pwm_frequency(0, 0)
+            .send()
+            .await;
+
+        assert!(matches!(result, Err(ObnizError::InvalidPin(12))));
+        assert!(mock.server().get_sent_messages().is_empty());
+
+        let result = Sequence::new(mock.clone()).send().await;
+        assert!(matches!(result, Err(ObnizError::Generic(_))));
+    }
+
+    #[tokio::test]
+    async fn test_sequence_is_sent_as_single_frame() {
+        let mock = MockObniz::new(MockConfig::default());
+        Sequence::new(mock.clone())
+            .io(1, true)
+            .wait(1)
+            .io(1, false)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            mock.server().get_sent_messages(),
+            vec![json!([{"io1": true}, {"system": {"wait": 1}}, {"io1": false}])]
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures::Stream;
+
+use crate::callbacks::Subscription;
+
+/// Values buffered per input stream unless `StreamOptions` says otherwise
+pub const DEFAULT_STREAM_CAPACITY: usize = 64;
+
+/// What an input stream does with a new value when its buffer is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Discard the oldest buffered value so the stream stays current
+    #[default]
+    DropOldest,
+    /// Discard the incoming value and keep what is already buffered
+    DropNewest,
+}
+
+/// Buffer settings for `changes()`, `voltages()`, `bytes()` and `events()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamOptions {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_STREAM_CAPACITY,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+impl StreamOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of values held before the overflow policy applies (at least 1)
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+}
+
+struct Buffer<T> {
+    queue: VecDeque<T>,
+    options: StreamOptions,
+    dropped: u64,
+    /// Live `StreamSink` handles; the stream ends once this is zero and the queue is empty
+    sinks: usize,
+    waker: Option<Waker>,
+}
+
+impl<T> Buffer<T> {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Writing end of an input stream, moved into a transport callback
+pub(crate) struct StreamSink<T> {
+    buffer: Arc<Mutex<Buffer<T>>>,
+}
+
+impl<T> StreamSink<T> {
+    pub(crate) fn new(options: StreamOptions) -> Self {
+        let options = StreamOptions {
+            capacity: options.capacity.max(1),
+            ..options
+        };
+        Self {
+            buffer: Arc::new(Mutex::new(Buffer {
+                queue: VecDeque::with_capacity(options.capacity),
+                options,
+                dropped: 0,
+                sinks: 1,
+                waker: None,
+            })),
+        }
+    }
+
+    /// Buffer `value`, applying the overflow policy when full
+    pub(crate) fn push(&self, value: T) {
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.queue.len() >= buffer.options.capacity {
+            buffer.dropped += 1;
+            match buffer.options.overflow {
+                OverflowPolicy::DropOldest => {
+                    buffer.queue.pop_front();
+                }
+                OverflowPolicy::DropNewest => return,
+            }
+        }
+        buffer.queue.push_back(value);
+        buffer.wake();
+    }
+
+    /// Reading end; it keeps `subscription` alive so the callback lives as long as the stream
+    pub(crate) fn into_stream(self, subscription: Subscription) -> InputStream<T> {
+        InputStream {
+            buffer: self.buffer.clone(),
+            _subscription: subscription,
+        }
+    }
+}
+
+impl<T> Clone for StreamSink<T> {
+    fn clone(&self) -> Self {
+        self.buffer.lock().unwrap().sinks += 1;
+        Self {
+            buffer: self.buffer.clone(),
+        }
+    }
+}
+
+impl<T> Drop for StreamSink<T> {
+    fn drop(&mut self) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.sinks -= 1;
+        if buffer.sinks == 0 {
+            buffer.wake();
+        }
+    }
+}
+
+/// Values from a streaming input, buffered according to `StreamOptions`.
+/// Dropping the stream unregisters its callback; the stream ends when the
+/// callback is removed (e.g. by `remove_callback` or disconnect).
+pub struct InputStream<T> {
+    buffer: Arc<Mutex<Buffer<T>>>,
+    _subscription: Subscription,
+}
+
+impl<T> InputStream<T> {
+    /// Number of values discarded by the overflow policy so far
+    pub fn dropped(&self) -> u64 {
+        self.buffer.lock().unwrap().dropped
+    }
+}
+
+impl<T> Stream for InputStream<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut buffer = self.buffer.lock().unwrap();
+        if let Some(value) = buffer.queue.pop_front() {
+            return Poll::Ready(Some(value));
+        }
+        if buffer.sinks == 0 {
+            return Poll::Ready(None);
+        }
+        buffer.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> std::fmt::Debug for InputStream<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let buffer = self.buffer.lock().unwrap();
+        f.debug_struct("InputStream")
+            .field("buffered", &buffer.queue.len())
+            .field("dropped", &buffer.dropped)
+            .field("options", &buffer.options)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::IoManager;
+    use crate::mock::{MockConfig, MockObniz};
+    use crate::switch::{SwitchAction, SwitchManager, SwitchResponse, SwitchState};
+    use crate::uart::UartManager;
+    use futures::StreamExt;
+    use serde_json::json;
+
+    fn detached() -> Subscription {
+        Subscription::new("io0".to_string(), 0, || {})
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_keeps_latest_values() {
+        let sink = StreamSink::new(StreamOptions::new().capacity(2));
+        let writer = sink.clone();
+        let mut stream = sink.into_stream(detached());
+
+        for value in 1..=4 {
+            writer.push(value);
+        }
+        assert_eq!(stream.dropped(), 2);
+        drop(writer);
+
+        assert_eq!(stream.by_ref().collect::<Vec<_>>().await, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_drop_newest_keeps_buffered_values() {
+        let sink = StreamSink::new(
+            StreamOptions::new()
+                .capacity(2)
+                .overflow(OverflowPolicy::DropNewest),
+        );
+        let writer = sink.clone();
+        let stream = sink.into_stream(detached());
+
+        for value in 1..=4 {
+            writer.push(value);
+        }
+        drop(writer);
+
+        assert_eq!(stream.collect::<Vec<_>>().await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_pending_stream_wakes_on_push() {
+        let sink = StreamSink::new(StreamOptions::default());
+        let writer = sink.clone();
+        let mut stream = sink.into_stream(detached());
+
+        let reader = tokio::spawn(async move { stream.next().await });
+        tokio::task::yield_now().await;
+        writer.push("value");
+
+        assert_eq!(reader.await.unwrap(), Some("value"));
+    }
+
+    #[tokio::test]
+    async fn test_input_streams() {
+        let mock = MockObniz::new(MockConfig::default());
+        let server = mock.server();
+
+        let mut changes = IoManager::new(mock.clone())
+            .pin(2)
+            .unwrap()
+            .changes()
+            .await
+            .unwrap();
+        let mut bytes = UartManager::new(mock.clone())
+            .channel(0)
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let switch = SwitchManager::new(mock.clone());
+        let mut events = switch.events().await.unwrap();
+
+        server.trigger_callback("io2", json!({"io2": true})).await;
+        server
+            .trigger_callback("uart0", json!({"uart0": {"data": [104, 105]}}))
+            .await;
+        server
+            .trigger_callback(
+                "switch",
+                json!({"switch": {"state": "push", "action": "push"}}),
+            )
+            .await;
+
+        assert_eq!(changes.next().await, Some(true));
+        assert_eq!(bytes.next().await, Some(104));
+        assert_eq!(bytes.next().await, Some(105));
+        assert_eq!(
+            events.next().await,
+            Some(SwitchResponse {
+                state: SwitchState::Push,
+                action: SwitchAction::Push,
+            })
+        );
+
+        // Removing the callbacks ends the stream
+        switch.remove_callback().unwrap();
+        assert_eq!(events.next().await, None);
+    }
+}
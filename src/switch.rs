@@ -1,9 +1,18 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tokio_tungstenite::tungstenite::protocol::Message;
 
-use crate::error::{ObnizError, ObnizResult};
+use crate::callbacks::{next_subscription_id, Subscription};
+use crate::error::{with_timeout, ObnizError, ObnizResult};
+use crate::gesture::{GestureConfig, GestureEvent, GestureRecognizer};
 use crate::obniz::Obniz;
+use crate::response::ObnizResponse;
+use crate::stream::{InputStream, StreamOptions, StreamSink};
+use crate::transport::ObnizTransport;
 
 /// Switch states for obniz board switch
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -38,7 +47,7 @@ pub enum SwitchAction {
 }
 
 /// Switch response structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SwitchResponse {
     pub state: SwitchState,
     pub action: SwitchAction,
@@ -46,12 +55,12 @@ pub struct SwitchResponse {
 
 /// Switch manager for obniz board switch
 #[derive(Debug, Clone)]
-pub struct SwitchManager {
-    obniz: Obniz,
+pub struct SwitchManager<T: ObnizTransport = Obniz> {
+    obniz: T,
 }
 
-impl SwitchManager {
-    pub fn new(obniz: Obniz) -> Self {
+impl<T: ObnizTransport> SwitchManager<T> {
+    pub fn new(obniz: T) -> Self {
         Self { obniz }
     }
 
@@ -60,39 +69,9 @@ impl SwitchManager {
         let request = json!([{"switch": "get"}]);
         let message = Message::from(request.to_string());
 
-        let response = self
-            .obniz
-            .send_await_response(message, "switch".to_string())
-            .await
-            .map_err(|e| ObnizError::Connection(e.to_string()))?;
-
-        // Parse the response to extract the switch state
-        // Response format is typically [{"switch": {"state": "none", "action": "get"}}]
-        if let Some(array) = response.as_array() {
-            if let Some(first_item) = array.first() {
-                if let Some(switch_data) = first_item.get("switch") {
-                    if let Some(state_value) = switch_data.get("state") {
-                        return serde_json::from_value(state_value.clone())
-                            .map_err(|e| ObnizError::JsonParse(e.to_string()));
-                    }
-                }
-            }
-        }
-
-        // Fallback: try direct object access
-        if let Some(switch_data) = response.get("switch") {
-            if let Some(state_value) = switch_data.get("state") {
-                serde_json::from_value(state_value.clone())
-                    .map_err(|e| ObnizError::JsonParse(e.to_string()))
-            } else {
-                Err(ObnizError::Generic(
-                    "No state field in switch response".to_string(),
-                ))
-            }
-        } else {
-            Err(ObnizError::Generic(
-                "No switch data in response".to_string(),
-            ))
+        match self.obniz.request(message, "switch".to_string()).await? {
+            ObnizResponse::Switch(switch) => Ok(switch.state),
+            other => Err(other.unexpected("switch")),
         }
     }
 
@@ -108,28 +87,20 @@ impl SwitchManager {
         Ok(state == direction)
     }
 
-    /// Register callback for switch state changes
-    pub async fn on_change<F>(&self, callback: F) -> ObnizResult<()>
+    /// Register callback for switch state changes, active until the subscription is dropped
+    pub async fn on_change<F>(&self, callback: F) -> ObnizResult<Subscription>
     where
         F: Fn(SwitchState, SwitchAction) + Send + Sync + 'static,
     {
-        self.obniz
-            .register_callback("switch".to_string(), move |response| {
-                if let Some(switch_data) = response.get("switch") {
-                    if let Ok(switch_response) =
-                        serde_json::from_value::<SwitchResponse>(switch_data.clone())
-                    {
-                        callback(switch_response.state, switch_response.action);
-                    }
-                }
-            })
-            .map_err(|e| ObnizError::CallbackError(e.to_string()))?;
-
-        Ok(())
+        self.obniz.subscribe("switch".to_string(), move |response| {
+            if let ObnizResponse::Switch(switch) = response {
+                callback(switch.state, switch.action);
+            }
+        })
     }
 
     /// Register callback for push events only
-    pub async fn on_push<F>(&self, callback: F) -> ObnizResult<()>
+    pub async fn on_push<F>(&self, callback: F) -> ObnizResult<Subscription>
     where
         F: Fn() + Send + Sync + 'static,
     {
@@ -142,7 +113,7 @@ impl SwitchManager {
     }
 
     /// Register callback for release events
-    pub async fn on_release<F>(&self, callback: F) -> ObnizResult<()>
+    pub async fn on_release<F>(&self, callback: F) -> ObnizResult<Subscription>
     where
         F: Fn() + Send + Sync + 'static,
     {
@@ -155,7 +126,7 @@ impl SwitchManager {
     }
 
     /// Register callback for left direction events
-    pub async fn on_left<F>(&self, callback: F) -> ObnizResult<()>
+    pub async fn on_left<F>(&self, callback: F) -> ObnizResult<Subscription>
     where
         F: Fn() + Send + Sync + 'static,
     {
@@ -168,7 +139,7 @@ impl SwitchManager {
     }
 
     /// Register callback for right direction events
-    pub async fn on_right<F>(&self, callback: F) -> ObnizResult<()>
+    pub async fn on_right<F>(&self, callback: F) -> ObnizResult<Subscription>
     where
         F: Fn() + Send + Sync + 'static,
     {
@@ -181,7 +152,7 @@ impl SwitchManager {
     }
 
     /// Register callback for any press event (push, left, or right)
-    pub async fn on_any_press<F>(&self, callback: F) -> ObnizResult<()>
+    pub async fn on_any_press<F>(&self, callback: F) -> ObnizResult<Subscription>
     where
         F: Fn(SwitchState) + Send + Sync + 'static,
     {
@@ -193,65 +164,162 @@ impl SwitchManager {
         .await
     }
 
-    /// Remove switch callback
+    /// Switch state changes as a stream; dropping it unregisters the callback
+    pub async fn events(&self) -> ObnizResult<InputStream<SwitchResponse>> {
+        self.events_with(StreamOptions::default()).await
+    }
+
+    /// `events` with custom buffering
+    pub async fn events_with(
+        &self,
+        options: StreamOptions,
+    ) -> ObnizResult<InputStream<SwitchResponse>> {
+        let sink = StreamSink::new(options);
+        let writer = sink.clone();
+        let subscription = self
+            .on_change(move |state, action| writer.push(SwitchResponse { state, action }))
+            .await?;
+        Ok(sink.into_stream(subscription))
+    }
+
+    /// Call `callback` for clicks, double clicks, long presses and hold-repeats.
+    /// Timing runs on the host clock; dropping the subscription stops recognition.
+    pub async fn on_gesture<F>(
+        &self,
+        config: GestureConfig,
+        callback: F,
+    ) -> ObnizResult<Subscription>
+    where
+        F: Fn(GestureEvent) + Send + Sync + 'static,
+    {
+        let mut events = self.events().await?;
+        let task = tokio::spawn(async move {
+            let mut recognizer = GestureRecognizer::new(config);
+            loop {
+                let next = match recognizer.next_deadline() {
+                    Some(deadline) => {
+                        let deadline = tokio::time::Instant::from_std(deadline);
+                        match tokio::time::timeout_at(deadline, events.next()).await {
+                            Ok(next) => next,
+                            Err(_) => {
+                                recognizer
+                                    .tick(std::time::Instant::now())
+                                    .into_iter()
+                                    .for_each(&callback);
+                                continue;
+                            }
+                        }
+                    }
+                    None => events.next().await,
+                };
+                let Some(event) = next else { break };
+                recognizer
+                    .update(event.state, std::time::Instant::now())
+                    .into_iter()
+                    .for_each(&callback);
+            }
+        });
+
+        Ok(Subscription::new(
+            "switch".to_string(),
+            next_subscription_id(),
+            move || task.abort(),
+        ))
+    }
+
+    /// Gestures as a stream; see `on_gesture`
+    pub async fn gestures(&self, config: GestureConfig) -> ObnizResult<InputStream<GestureEvent>> {
+        let sink = StreamSink::new(StreamOptions::default());
+        let writer = sink.clone();
+        let subscription = self
+            .on_gesture(config, move |gesture| writer.push(gesture))
+            .await?;
+        Ok(sink.into_stream(subscription))
+    }
+
+    /// Remove every switch callback
     pub fn remove_callback(&self) -> ObnizResult<()> {
-        self.obniz
-            .unregister_callback("switch".to_string())
-            .map_err(|e| ObnizError::CallbackError(e.to_string()))
+        self.obniz.unregister_callback("switch".to_string())
     }
 
-    /// Wait for specific switch state (blocking until state is reached)
+    /// Wait until the switch reaches `target_state`, returning at once if it already has.
+    /// Driven by the switch events the device pushes, so short presses are not missed.
     pub async fn wait_for_state(
         &self,
         target_state: SwitchState,
         timeout_ms: Option<u64>,
     ) -> ObnizResult<()> {
-        use tokio::time::{sleep, timeout, Duration};
-
-        let check_interval = Duration::from_millis(50);
-        let max_duration = timeout_ms.map(Duration::from_millis);
+        wait_with_timeout(timeout_ms, async {
+            // Subscribe before reading the state so a change in between is not lost
+            let mut events = self.events().await?;
+            if self.get_state().await? == target_state {
+                return Ok(());
+            }
 
-        let wait_future = async {
-            loop {
-                let current_state = self.get_state().await?;
-                if current_state == target_state {
+            while let Some(event) = events.next().await {
+                if event.state == target_state {
                     return Ok(());
                 }
-                sleep(check_interval).await;
             }
-        };
-
-        match max_duration {
-            Some(duration) => timeout(duration, wait_future)
-                .await
-                .map_err(|_| ObnizError::Timeout)?,
-            None => wait_future.await,
-        }
+            Err(events_closed())
+        })
+        .await
     }
 
-    /// Wait for any press event
+    /// Wait for any press event, returning at once if the switch is already pressed
     pub async fn wait_for_press(&self, timeout_ms: Option<u64>) -> ObnizResult<SwitchState> {
-        use tokio::time::{sleep, timeout, Duration};
-
-        let check_interval = Duration::from_millis(50);
-        let max_duration = timeout_ms.map(Duration::from_millis);
+        wait_with_timeout(timeout_ms, async {
+            let mut events = self.events().await?;
+            let current_state = self.get_state().await?;
+            if current_state != SwitchState::None {
+                return Ok(current_state);
+            }
 
-        let wait_future = async {
-            loop {
-                let current_state = self.get_state().await?;
-                if current_state != SwitchState::None {
-                    return Ok(current_state);
+            while let Some(event) = events.next().await {
+                if is_press(&event) {
+                    return Ok(event.state);
                 }
-                sleep(check_interval).await;
             }
-        };
+            Err(events_closed())
+        })
+        .await
+    }
 
-        match max_duration {
-            Some(duration) => timeout(duration, wait_future)
-                .await
-                .map_err(|_| ObnizError::Timeout)?,
-            None => wait_future.await,
+    /// Wait until the latest presses match `sequence`, e.g. left, left, push.
+    /// Releases are ignored and only presses after the call count.
+    pub async fn wait_for_sequence(
+        &self,
+        sequence: &[SwitchState],
+        timeout_ms: Option<u64>,
+    ) -> ObnizResult<()> {
+        if sequence.contains(&SwitchState::None) {
+            return Err(ObnizError::Generic(
+                "Switch sequences can only contain presses".to_string(),
+            ));
+        }
+        if sequence.is_empty() {
+            return Ok(());
         }
+
+        wait_with_timeout(timeout_ms, async {
+            let mut events = self.events().await?;
+            let mut recent = VecDeque::with_capacity(sequence.len());
+
+            while let Some(event) = events.next().await {
+                if !is_press(&event) {
+                    continue;
+                }
+                if recent.len() == sequence.len() {
+                    recent.pop_front();
+                }
+                recent.push_back(event.state);
+                if recent.iter().eq(sequence.iter()) {
+                    return Ok(());
+                }
+            }
+            Err(events_closed())
+        })
+        .await
     }
 
     /// Wait for release event
@@ -260,9 +328,29 @@ impl SwitchManager {
     }
 }
 
+/// A new press pushed by the device, as opposed to a release or a `get` reply
+fn is_press(event: &SwitchResponse) -> bool {
+    event.state != SwitchState::None && event.action != SwitchAction::Get
+}
+
+fn events_closed() -> ObnizError {
+    ObnizError::Connection("Switch events stopped before the wait finished".to_string())
+}
+
+async fn wait_with_timeout<F, R>(timeout_ms: Option<u64>, wait: F) -> ObnizResult<R>
+where
+    F: std::future::Future<Output = ObnizResult<R>>,
+{
+    match timeout_ms {
+        Some(timeout_ms) => with_timeout(wait, Duration::from_millis(timeout_ms)).await,
+        None => wait.await,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock::{MockConfig, MockObniz};
 
     #[test]
     fn test_switch_state_serialization() {
@@ -374,4 +462,52 @@ mod tests {
         assert_ne!(SwitchState::None, SwitchState::Push);
         assert_ne!(SwitchState::Left, SwitchState::Right);
     }
+
+    #[tokio::test]
+    async fn test_switch_waits_follow_pushed_events() {
+        let mock = MockObniz::new(MockConfig::default());
+        let switch = SwitchManager::new(mock.clone());
+
+        // A press released straight away is still seen
+        let server = mock.server();
+        let presses = tokio::spawn(async move {
+            server
+                .press_switch(&[("right", "right"), ("none", "release")])
+                .await
+        });
+        assert_eq!(
+            switch.wait_for_press(Some(1000)).await.unwrap(),
+            SwitchState::Right
+        );
+        presses.await.unwrap();
+
+        let server = mock.server();
+        let presses = tokio::spawn(async move {
+            server
+                .press_switch(&[
+                    ("left", "left"),
+                    ("none", "release"),
+                    ("left", "left"),
+                    ("none", "release"),
+                    ("left", "left"),
+                    ("push", "push"),
+                ])
+                .await
+        });
+        switch
+            .wait_for_sequence(
+                &[SwitchState::Left, SwitchState::Left, SwitchState::Push],
+                Some(1000),
+            )
+            .await
+            .unwrap();
+        presses.await.unwrap();
+
+        assert!(matches!(
+            switch.wait_for_state(SwitchState::Push, Some(20)).await,
+            Err(ObnizError::Timeout)
+        ));
+        // The mock reports "none", so waiting for a release returns at once
+        switch.wait_for_release(Some(20)).await.unwrap();
+    }
 }
@@ -4,6 +4,8 @@ use tokio_tungstenite::tungstenite::protocol::Message;
 
 use crate::error::{ObnizError, ObnizResult};
 use crate::obniz::Obniz;
+use crate::response::ObnizResponse;
+use crate::transport::ObnizTransport;
 
 /// System information structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,12 +24,12 @@ pub struct ResetConfig {
 
 /// System manager for obniz device control
 #[derive(Debug, Clone)]
-pub struct SystemManager {
-    obniz: Obniz,
+pub struct SystemManager<T: ObnizTransport = Obniz> {
+    obniz: T,
 }
 
-impl SystemManager {
-    pub fn new(obniz: Obniz) -> Self {
+impl<T: ObnizTransport> SystemManager<T> {
+    pub fn new(obniz: T) -> Self {
         Self { obniz }
     }
 
@@ -36,9 +38,7 @@ impl SystemManager {
         let request = json!([{"system": {"reset": true}}]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Get system information
@@ -46,31 +46,10 @@ impl SystemManager {
         let request = json!([{"system": {"info": "get"}}]);
         let message = Message::from(request.to_string());
 
-        let response = self
-            .obniz
-            .send_await_response(message, "system".to_string())
-            .await
-            .map_err(|e| ObnizError::Connection(e.to_string()))?;
-
-        // Parse the response to extract the system information
-        // Response format is typically [{"system": {...}}]
-        if let Some(array) = response.as_array() {
-            if let Some(first_item) = array.first() {
-                if let Some(system_info) = first_item.get("system") {
-                    return serde_json::from_value(system_info.clone())
-                        .map_err(|e| ObnizError::JsonParse(e.to_string()));
-                }
-            }
-        }
-
-        // Fallback: try direct object access
-        if let Some(system_info) = response.get("system") {
-            serde_json::from_value(system_info.clone())
-                .map_err(|e| ObnizError::JsonParse(e.to_string()))
-        } else {
-            Err(ObnizError::Generic(
-                "No system information in response".to_string(),
-            ))
+        match self.obniz.request(message, "system".to_string()).await? {
+            ObnizResponse::System(system_info) => serde_json::from_value(system_info)
+                .map_err(|e| ObnizError::JsonParse(e.to_string())),
+            other => Err(other.unexpected("system")),
         }
     }
 
@@ -79,9 +58,7 @@ impl SystemManager {
         let request = json!([{"ws": {"reset_obniz_on_ws_disconnection": enable}}]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Keep alive - prevent auto sleep
@@ -89,9 +66,7 @@ impl SystemManager {
         let request = json!([{"system": {"keep_working_at_offline": true}}]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Set ping interval for connection monitoring
@@ -105,9 +80,7 @@ impl SystemManager {
         let request = json!([{"ws": {"ping": {"interval": interval_ms}}}]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Get device status
@@ -118,8 +91,7 @@ impl SystemManager {
         let response = self
             .obniz
             .send_await_response(message, "system".to_string())
-            .await
-            .map_err(|e| ObnizError::Connection(e.to_string()))?;
+            .await?;
 
         Ok(response)
     }
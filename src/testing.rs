@@ -0,0 +1,87 @@
+//! In-process stand-in for an obniz device, used by the unit tests
+
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::options::ObnizBuilder;
+
+pub(crate) const FAKE_DEVICE_ID: &str = "0000-0000";
+
+/// Frames the device received and a channel to push frames to the client
+pub(crate) struct FakeDevice {
+    pub frames: mpsc::UnboundedReceiver<Value>,
+    pub push: mpsc::UnboundedSender<Value>,
+}
+
+impl FakeDevice {
+    /// Next frame sent by the client
+    pub async fn next_frame(&mut self) -> Value {
+        tokio::time::timeout(std::time::Duration::from_secs(5), self.frames.recv())
+            .await
+            .expect("no frame received")
+            .expect("fake device stopped")
+    }
+}
+
+/// Start a device on localhost that answers each frame with `respond`.
+/// Returns a builder pointing at it and a handle to observe traffic.
+pub(crate) async fn fake_device<F>(respond: F) -> (ObnizBuilder, FakeDevice)
+where
+    F: Fn(&Value) -> Option<Value> + Send + 'static,
+{
+    let ready = json!([{"ws": {"ready": true, "obniz": {"hw": "obnizb1", "firmware": "3.5.0"}}}]);
+    fake_device_with_ready(Some(ready), respond).await
+}
+
+/// Like `fake_device`, sending `ready` (if any) as the first frame
+pub(crate) async fn fake_device_with_ready<F>(
+    ready: Option<Value>,
+    respond: F,
+) -> (ObnizBuilder, FakeDevice)
+where
+    F: Fn(&Value) -> Option<Value> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (frame_tx, frames) = mpsc::unbounded_channel();
+    let (push, mut push_rx) = mpsc::unbounded_channel::<Value>();
+
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+        if let Some(ready) = ready {
+            let _ = socket.send(Message::from(ready.to_string())).await;
+        }
+
+        loop {
+            tokio::select! {
+                message = socket.next() => {
+                    let Some(Ok(Message::Text(text))) = message else {
+                        break;
+                    };
+                    let frame: Value = serde_json::from_str(&text).unwrap();
+                    let reply = respond(&frame);
+                    let _ = frame_tx.send(frame);
+                    if let Some(reply) = reply {
+                        let _ = socket.send(Message::from(reply.to_string())).await;
+                    }
+                }
+                pushed = push_rx.recv() => {
+                    let Some(frame) = pushed else {
+                        break;
+                    };
+                    let _ = socket.send(Message::from(frame.to_string())).await;
+                }
+            }
+        }
+    });
+
+    let builder = ObnizBuilder::new(FAKE_DEVICE_ID)
+        .host(&format!("ws://{addr}"))
+        .allow_insecure(true)
+        .skip_redirect(true);
+    (builder, FakeDevice { frames, push })
+}
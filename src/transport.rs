@@ -0,0 +1,120 @@
+use std::future::Future;
+
+use serde_json::Value;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::callbacks::Subscription;
+use crate::error::{ObnizError, ObnizResult};
+use crate::hardware::HardwareProfile;
+use crate::obniz::Obniz;
+use crate::resources::ResourceRegistry;
+use crate::response::ObnizResponse;
+
+/// Message transport used by the managers, implemented by `Obniz` and `MockObniz`
+pub trait ObnizTransport: Clone + Send + Sync + 'static {
+    /// Send a message without waiting for a response
+    fn send_message(&self, message: Message) -> ObnizResult<()>;
+
+    /// Send a message and wait for the response under `response_key`, as `[{key: value}]`
+    fn send_await_response(
+        &self,
+        message: Message,
+        response_key: String,
+    ) -> impl Future<Output = ObnizResult<Value>> + Send;
+
+    /// Send a message and wait for the typed response under `response_key`
+    fn request(
+        &self,
+        message: Message,
+        response_key: String,
+    ) -> impl Future<Output = ObnizResult<ObnizResponse>> + Send {
+        async move {
+            let frame = self
+                .send_await_response(message, response_key.clone())
+                .await?;
+            ObnizResponse::from_frame(&frame, &response_key)
+        }
+    }
+
+    /// Send one message and wait for the typed response under each of `response_keys`, in order
+    fn request_all(
+        &self,
+        message: Message,
+        response_keys: Vec<String>,
+    ) -> impl Future<Output = ObnizResult<Vec<ObnizResponse>>> + Send;
+
+    /// Register a callback for messages under `key` that lives as long as the subscription
+    fn subscribe<F>(&self, key: String, callback: F) -> ObnizResult<Subscription>
+    where
+        F: Fn(ObnizResponse) + Send + Sync + 'static;
+
+    /// Register a persistent callback for messages under `key`
+    fn register_callback<F>(&self, key: String, callback: F) -> ObnizResult<()>
+    where
+        F: Fn(ObnizResponse) + Send + Sync + 'static,
+    {
+        self.subscribe(key, callback).map(Subscription::detach)
+    }
+
+    /// Remove every callback registered under `key`
+    fn unregister_callback(&self, key: String) -> ObnizResult<()>;
+
+    /// Pin ownership shared by every manager of this device
+    fn resources(&self) -> &ResourceRegistry;
+
+    /// Board the managers validate pins and peripherals against
+    fn hardware_profile(&self) -> HardwareProfile {
+        HardwareProfile::default()
+    }
+}
+
+impl ObnizTransport for Obniz {
+    fn send_message(&self, message: Message) -> ObnizResult<()> {
+        Obniz::send_message(self, message).map_err(|e| ObnizError::Connection(e.to_string()))
+    }
+
+    async fn send_await_response(
+        &self,
+        message: Message,
+        response_key: String,
+    ) -> ObnizResult<Value> {
+        Obniz::send_await_response(self, message, response_key)
+            .await
+            .map_err(|e| {
+                // Keep `ObnizError::Timeout` and friends intact
+                e.downcast::<ObnizError>()
+                    .unwrap_or_else(|e| ObnizError::Connection(e.to_string()))
+            })
+    }
+
+    async fn request(&self, message: Message, response_key: String) -> ObnizResult<ObnizResponse> {
+        Obniz::request(self, message, response_key).await
+    }
+
+    async fn request_all(
+        &self,
+        message: Message,
+        response_keys: Vec<String>,
+    ) -> ObnizResult<Vec<ObnizResponse>> {
+        Obniz::request_all(self, message, response_keys).await
+    }
+
+    fn subscribe<F>(&self, key: String, callback: F) -> ObnizResult<Subscription>
+    where
+        F: Fn(ObnizResponse) + Send + Sync + 'static,
+    {
+        Obniz::subscribe(self, key, callback).map_err(|e| ObnizError::CallbackError(e.to_string()))
+    }
+
+    fn unregister_callback(&self, key: String) -> ObnizResult<()> {
+        Obniz::unregister_callback(self, key).map_err(|e| ObnizError::CallbackError(e.to_string()))
+    }
+
+    fn resources(&self) -> &ResourceRegistry {
+        Obniz::resources(self)
+    }
+
+    fn hardware_profile(&self) -> HardwareProfile {
+        Obniz::hardware_profile(self)
+    }
+}
@@ -1,9 +1,15 @@
+use futures::{FutureExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use tokio_tungstenite::tungstenite::protocol::Message;
 
+use crate::callbacks::Subscription;
 use crate::error::{ObnizError, ObnizResult};
 use crate::obniz::Obniz;
+use crate::resources::PinClaim;
+use crate::response::ObnizResponse;
+use crate::stream::{InputStream, StreamOptions, StreamSink};
+use crate::transport::ObnizTransport;
 
 /// UART parity settings
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -55,16 +61,40 @@ impl Default for UartConfig {
     }
 }
 
+impl UartConfig {
+    /// IO pins the configuration uses: rx, tx and the flow control pins if set
+    pub fn pins(&self) -> Vec<u8> {
+        [
+            Some(self.rx_pin),
+            Some(self.tx_pin),
+            self.rts_pin,
+            self.cts_pin,
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
 /// UART communication manager
 #[derive(Debug)]
-pub struct UartChannel {
+pub struct UartChannel<T: ObnizTransport = Obniz> {
     channel: u8,
-    obniz: Obniz,
+    obniz: T,
+    /// Received bytes not yet taken by `read`, started on the first `read`
+    receiver: Option<InputStream<u8>>,
+    /// Handle of a `UartAllocation`, the only one that may init or deinit its channel
+    allocated: bool,
 }
 
-impl UartChannel {
-    pub fn new(channel: u8, obniz: Obniz) -> Self {
-        Self { channel, obniz }
+impl<T: ObnizTransport> UartChannel<T> {
+    pub fn new(channel: u8, obniz: T) -> Self {
+        Self {
+            channel,
+            obniz,
+            receiver: None,
+            allocated: false,
+        }
     }
 
     pub fn channel_key(&self) -> String {
@@ -74,20 +104,11 @@ impl UartChannel {
     /// Initialize UART with configuration
     pub async fn init(&self, config: UartConfig) -> ObnizResult<()> {
         // Validate pins
-        if config.rx_pin > 11 || config.tx_pin > 11 {
-            return Err(ObnizError::Generic("UART pins must be 0-11".to_string()));
-        }
-
-        if let Some(rts_pin) = config.rts_pin {
-            if rts_pin > 11 {
-                return Err(ObnizError::InvalidPin(rts_pin));
-            }
-        }
-
-        if let Some(cts_pin) = config.cts_pin {
-            if cts_pin > 11 {
-                return Err(ObnizError::InvalidPin(cts_pin));
-            }
+        let hardware = self.obniz.hardware_profile();
+        hardware.check_io(config.rx_pin)?;
+        hardware.check_io(config.tx_pin)?;
+        for pin in [config.rts_pin, config.cts_pin].into_iter().flatten() {
+            hardware.check_io(pin)?;
         }
 
         // Validate baud rate
@@ -112,6 +133,8 @@ impl UartChannel {
         }
 
         let channel_key = self.channel_key();
+        let claim = PinClaim::exact(&channel_key, &config.pins()).by_allocation(self.allocated);
+
         let mut uart_config = json!({
             "rx": config.rx_pin,
             "tx": config.tx_pin,
@@ -133,25 +156,38 @@ impl UartChannel {
         let message = Message::from(request.to_string());
 
         self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+            .resources()
+            .with_claims(&[claim], || self.obniz.send_message(message))
     }
 
-    /// Send data via UART
-    pub async fn send(&self, data: Vec<u8>) -> ObnizResult<()> {
+    /// Fail with `ChannelInUse` if a live allocation owns the channel and this handle is not it
+    fn check_access(&self) -> ObnizResult<()> {
+        if self.allocated {
+            return Ok(());
+        }
+        self.obniz
+            .resources()
+            .check_unallocated(&self.channel_key())
+    }
+
+    /// Command object for `send`, e.g. `{"uart0": {"data": [1, 2]}}`
+    pub fn send_command(&self, data: &[u8]) -> ObnizResult<Value> {
         if data.is_empty() {
             return Err(ObnizError::Generic("Data cannot be empty".to_string()));
         }
+        self.check_access()?;
 
         // Data validation: u8 values are inherently 0-255, no additional check needed
 
-        let channel_key = self.channel_key();
-        let request = json!([{&channel_key: {"data": data}}]);
+        Ok(json!({self.channel_key(): {"data": data}}))
+    }
+
+    /// Send data via UART
+    pub async fn send(&self, data: Vec<u8>) -> ObnizResult<()> {
+        let request = json!([self.send_command(&data)?]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)
     }
 
     /// Send string data via UART (converted to UTF-8 bytes)
@@ -160,36 +196,20 @@ impl UartChannel {
         self.send(data).await
     }
 
-    /// Register callback for received data
-    pub async fn on_receive<F>(&self, callback: F) -> ObnizResult<()>
+    /// Register callback for received data, active until the subscription is dropped
+    pub async fn on_receive<F>(&self, callback: F) -> ObnizResult<Subscription>
     where
         F: Fn(Vec<u8>) + Send + Sync + 'static,
     {
-        let channel_key = self.channel_key();
-        let channel_key_clone = channel_key.clone();
-
-        self.obniz
-            .register_callback(channel_key, move |response| {
-                if let Some(uart_data) = response.get(&channel_key_clone) {
-                    if let Some(data_array) = uart_data.get("data") {
-                        if let Some(data_vec) = data_array.as_array() {
-                            let bytes: Vec<u8> = data_vec
-                                .iter()
-                                .filter_map(|v| v.as_u64())
-                                .map(|v| v as u8)
-                                .collect();
-                            callback(bytes);
-                        }
-                    }
-                }
-            })
-            .map_err(|e| ObnizError::CallbackError(e.to_string()))?;
-
-        Ok(())
+        self.obniz.subscribe(self.channel_key(), move |response| {
+            if let ObnizResponse::Uart { data, .. } = response {
+                callback(data);
+            }
+        })
     }
 
     /// Register callback for received string data
-    pub async fn on_receive_string<F>(&self, callback: F) -> ObnizResult<()>
+    pub async fn on_receive_string<F>(&self, callback: F) -> ObnizResult<Subscription>
     where
         F: Fn(String) + Send + Sync + 'static,
     {
@@ -201,51 +221,162 @@ impl UartChannel {
         .await
     }
 
-    /// Remove receive callback
+    /// Received bytes as a stream; the overflow policy counts single bytes
+    pub async fn bytes(&self) -> ObnizResult<InputStream<u8>> {
+        self.bytes_with(StreamOptions::default()).await
+    }
+
+    /// `bytes` with custom buffering
+    pub async fn bytes_with(&self, options: StreamOptions) -> ObnizResult<InputStream<u8>> {
+        let sink = StreamSink::new(options);
+        let writer = sink.clone();
+        let subscription = self
+            .on_receive(move |data| data.into_iter().for_each(|byte| writer.push(byte)))
+            .await?;
+        Ok(sink.into_stream(subscription))
+    }
+
+    /// Wait for received data and copy as much as is buffered into `buf`.
+    /// Bytes arriving after the first call are kept until read; returns 0 once
+    /// reception stops (e.g. `remove_callback` or disconnect).
+    pub async fn read(&mut self, buf: &mut [u8]) -> ObnizResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let receiver = match self.receiver.take() {
+            Some(receiver) => receiver,
+            None => self.bytes().await?,
+        };
+        let receiver = self.receiver.insert(receiver);
+
+        let Some(first) = receiver.next().await else {
+            return Ok(0);
+        };
+        buf[0] = first;
+        let mut len = 1;
+        while len < buf.len() {
+            match receiver.next().now_or_never() {
+                Some(Some(byte)) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                _ => break,
+            }
+        }
+        Ok(len)
+    }
+
+    /// Remove every receive callback
     pub fn remove_callback(&self) -> ObnizResult<()> {
+        self.check_access()?;
         let channel_key = self.channel_key();
-        self.obniz
-            .unregister_callback(channel_key)
-            .map_err(|e| ObnizError::CallbackError(e.to_string()))
+        self.obniz.unregister_callback(channel_key)
     }
 
     /// Deinitialize UART channel
     pub async fn deinit(&self) -> ObnizResult<()> {
+        self.check_access()?;
         let channel_key = self.channel_key();
         let request = json!([{&channel_key: null}]);
         let message = Message::from(request.to_string());
 
-        self.obniz
-            .send_message(message)
-            .map_err(|e| ObnizError::Connection(e.to_string()))
+        self.obniz.send_message(message)?;
+        self.obniz.resources().release(&channel_key);
+        Ok(())
+    }
+}
+
+/// UART channel picked by `UartManager::allocate`; dropping it deinitializes the
+/// channel and frees it and its pins for the next allocation
+#[derive(Debug)]
+pub struct UartAllocation<T: ObnizTransport = Obniz> {
+    channel: UartChannel<T>,
+}
+
+impl<T: ObnizTransport> UartAllocation<T> {
+    /// Number of the allocated channel
+    pub fn channel(&self) -> u8 {
+        self.channel.channel
+    }
+}
+
+impl<T: ObnizTransport> std::ops::Deref for UartAllocation<T> {
+    type Target = UartChannel<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.channel
+    }
+}
+
+impl<T: ObnizTransport> std::ops::DerefMut for UartAllocation<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.channel
+    }
+}
+
+impl<T: ObnizTransport> Drop for UartAllocation<T> {
+    fn drop(&mut self) {
+        let channel_key = self.channel.channel_key();
+        let request = json!([{&channel_key: null}]);
+        let _ = self
+            .channel
+            .obniz
+            .send_message(Message::from(request.to_string()));
+        self.channel
+            .obniz
+            .resources()
+            .release_allocation(&channel_key);
     }
 }
 
 /// UART manager for handling multiple channels
 #[derive(Debug, Clone)]
-pub struct UartManager {
-    obniz: Obniz,
+pub struct UartManager<T: ObnizTransport = Obniz> {
+    obniz: T,
 }
 
-impl UartManager {
-    pub fn new(obniz: Obniz) -> Self {
+impl<T: ObnizTransport> UartManager<T> {
+    pub fn new(obniz: T) -> Self {
         Self { obniz }
     }
 
     /// Get specific UART channel (typically uart0)
-    pub fn channel(&self, channel: u8) -> ObnizResult<UartChannel> {
-        // Most obniz devices support uart0, some may support more
-        if channel > 2 {
-            return Err(ObnizError::Generic("UART channel must be 0-2".to_string()));
-        }
+    pub fn channel(&self, channel: u8) -> ObnizResult<UartChannel<T>> {
+        self.obniz.hardware_profile().check_uart(channel)?;
         Ok(UartChannel::new(channel, self.obniz.clone()))
     }
 
     /// Get primary UART channel (uart0)
-    pub fn uart0(&self) -> UartChannel {
+    pub fn uart0(&self) -> UartChannel<T> {
         UartChannel::new(0, self.obniz.clone())
     }
 
+    /// Pick a free channel and initialize it with `config`
+    pub async fn allocate(&self, config: UartConfig) -> ObnizResult<UartAllocation<T>> {
+        let hardware = self.obniz.hardware_profile();
+        let pins = config.pins();
+        for pin in &pins {
+            hardware.check_io(*pin)?;
+        }
+        let channel = self
+            .obniz
+            .resources()
+            .allocate("uart", hardware.uart_channels, &pins)?;
+
+        let channel = UartChannel {
+            allocated: true,
+            ..UartChannel::new(channel, self.obniz.clone())
+        };
+        // The channel was never initialized, so free it without sending a deinit
+        if let Err(e) = channel.init(config).await {
+            self.obniz
+                .resources()
+                .release_allocation(&channel.channel_key());
+            return Err(e);
+        }
+        Ok(UartAllocation { channel })
+    }
+
     /// Initialize UART channel with configuration
     pub async fn init_channel(&self, channel: u8, config: UartConfig) -> ObnizResult<()> {
         self.channel(channel)?.init(config).await
@@ -262,7 +393,11 @@ impl UartManager {
     }
 
     /// Set receive callback for specific channel
-    pub async fn set_receive_callback<F>(&self, channel: u8, callback: F) -> ObnizResult<()>
+    pub async fn set_receive_callback<F>(
+        &self,
+        channel: u8,
+        callback: F,
+    ) -> ObnizResult<Subscription>
     where
         F: Fn(Vec<u8>) + Send + Sync + 'static,
     {
@@ -270,7 +405,11 @@ impl UartManager {
     }
 
     /// Set string receive callback for specific channel
-    pub async fn set_string_callback<F>(&self, channel: u8, callback: F) -> ObnizResult<()>
+    pub async fn set_string_callback<F>(
+        &self,
+        channel: u8,
+        callback: F,
+    ) -> ObnizResult<Subscription>
     where
         F: Fn(String) + Send + Sync + 'static,
     {
@@ -286,7 +425,9 @@ impl UartManager {
     pub async fn deinit_channel(&self, channel: u8) -> ObnizResult<()> {
         self.channel(channel)?.deinit().await
     }
+}
 
+impl UartManager {
     /// Create simple UART configuration for common use cases
     pub fn simple_config(rx_pin: u8, tx_pin: u8, baud_rate: u32) -> UartConfig {
         UartConfig {
@@ -320,6 +461,7 @@ impl UartManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock::{MockConfig, MockObniz};
 
     #[test]
     fn test_uart_config_default() {
@@ -403,4 +545,49 @@ mod tests {
         assert_eq!(format!("uart{}", 0), "uart0");
         assert_eq!(format!("uart{}", 1), "uart1");
     }
+
+    #[tokio::test]
+    async fn test_failed_allocation_init_sends_no_deinit() {
+        let mock = MockObniz::new(MockConfig::default());
+        let uart = UartManager::new(mock.clone());
+
+        assert!(uart
+            .allocate(UartManager::simple_config(6, 7, 0))
+            .await
+            .is_err());
+        assert!(!mock.resources().is_allocated("uart0"));
+        assert!(mock.server().get_sent_messages().is_empty());
+
+        let serial = uart
+            .allocate(UartManager::simple_config(6, 7, 9600))
+            .await
+            .unwrap();
+        assert_eq!(serial.channel(), 0);
+        assert!(matches!(
+            uart.channel(0).unwrap().send(vec![1]).await,
+            Err(ObnizError::ChannelInUse { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_channel_allocation_guards() {
+        let mock = MockObniz::new(MockConfig::default());
+        let uart = UartManager::new(mock.clone());
+
+        let serial = uart
+            .allocate(UartManager::simple_config(6, 7, 9600))
+            .await
+            .unwrap();
+        assert_eq!(serial.channel(), 0);
+        drop(serial);
+        assert!(!mock.resources().is_claimed("uart0"));
+
+        assert_eq!(
+            mock.server().get_sent_messages(),
+            vec![
+                json!([{"uart0": {"rx": 6, "tx": 7, "baud": 9600, "stop": 1.0, "bits": 8, "parity": "off"}}]),
+                json!([{"uart0": null}]),
+            ]
+        );
+    }
 }